@@ -11,6 +11,10 @@ impl AllPass {
         }
     }
 
+    pub fn reset(&mut self) {
+        self.delay_buffer.reset();
+    }
+
     pub fn process(&mut self, input: f32, feedback: f32) -> f32 {
         let delayed = self.delay_buffer.read();
         self.delay_buffer.write(input + delayed * feedback);
@@ -0,0 +1,108 @@
+// Encodes the reverb into first order Ambisonics in the AmbiX format, that is
+// W Y Z X in ACN order with SN3D normalization. For a diffuse field the tail is
+// spread over virtual sources at the corners of a cube around the listener, each
+// with its own decorrelated copy of the tail. Without diffuseness the tail
+// comes from a stereo pair in front, like it would on speakers.
+
+use crate::surround::Decorrelator;
+
+// Elevation of the cube corners, atan(1 / sqrt(2)) in degrees
+const CORNER_ELEVATION: f32 = 35.264;
+// Azimuth of the left channel of the front stereo pair in degrees
+const PAIR_AZIMUTH: f32 = 30.0;
+// Keeps the diffuse field at the same level as the stereo pair, the eight
+// sources add up in power while the pair is only two
+const DIFFUSE_GAIN: f32 = 0.5;
+
+/// The AmbiX gains of a plane wave from `azimuth` and `elevation` in degrees.
+/// Azimuth goes counterclockwise from the front, elevation up from the horizon.
+pub fn encode(azimuth: f32, elevation: f32) -> [f32; 4] {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+
+    [
+        1.0,
+        azimuth.sin() * elevation.cos(),
+        elevation.sin(),
+        azimuth.cos() * elevation.cos(),
+    ]
+}
+
+/// Rotates AmbiX `channels` by `angle` radians around the vertical axis.
+fn rotate(channels: [f32; 4], angle: f32) -> [f32; 4] {
+    let (sin, cos) = angle.sin_cos();
+    let [w, y, z, x] = channels;
+    [w, x * sin + y * cos, z, x * cos - y * sin]
+}
+
+pub struct Encoder {
+    // Every virtual source is fed from the side of the stereo tail it is on
+    sources: [([f32; 4], bool); 8],
+    decorrelators: [Decorrelator; 8],
+    pair: ([f32; 4], [f32; 4]),
+    diffuseness: f32,
+    // Rotation around the vertical axis in radians
+    orientation: f32,
+}
+
+impl Encoder {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let corner = |azimuth: f32, elevation: f32| (encode(azimuth, elevation), azimuth > 0.0);
+
+        Self {
+            sources: [
+                corner(45.0, CORNER_ELEVATION),
+                corner(-45.0, CORNER_ELEVATION),
+                corner(135.0, CORNER_ELEVATION),
+                corner(-135.0, CORNER_ELEVATION),
+                corner(45.0, -CORNER_ELEVATION),
+                corner(-45.0, -CORNER_ELEVATION),
+                corner(135.0, -CORNER_ELEVATION),
+                corner(-135.0, -CORNER_ELEVATION),
+            ],
+            decorrelators: std::array::from_fn(|spread| Decorrelator::new(sample_rate, spread)),
+            pair: (encode(PAIR_AZIMUTH, 0.0), encode(-PAIR_AZIMUTH, 0.0)),
+            diffuseness: 1.0,
+            orientation: 0.0,
+        }
+    }
+
+    /// Sets how much of the tail comes from all around instead of the front
+    /// pair, from 0.0 to 1.0
+    pub fn diffuseness(&mut self, diffuseness: f32) {
+        self.diffuseness = diffuseness;
+    }
+
+    /// Sets the rotation of the sound field around the listener in degrees
+    pub fn orientation(&mut self, orientation: f32) {
+        self.orientation = orientation.to_radians();
+    }
+
+    pub fn reset(&mut self) {
+        for decorrelator in self.decorrelators.iter_mut() {
+            decorrelator.reset();
+        }
+    }
+
+    /// Encodes the stereo `wet` signal with the current diffuseness and the
+    /// `dry` signal as the front pair, returns the four AmbiX channels.
+    pub fn process(&mut self, wet: (f32, f32), dry: (f32, f32)) -> [f32; 4] {
+        let direct = 1.0 - self.diffuseness;
+        let (left, right) = (wet.0 * direct + dry.0, wet.1 * direct + dry.1);
+        let mut out: [f32; 4] = std::array::from_fn(|ch| left * self.pair.0[ch] + right * self.pair.1[ch]);
+
+        if self.diffuseness > 0.0 {
+            let gain = self.diffuseness * DIFFUSE_GAIN;
+            for ((encoding, on_left), decorrelator) in self.sources.iter().zip(self.decorrelators.iter_mut()) {
+                let source = decorrelator.process(if *on_left { wet.0 } else { wet.1 }) * gain;
+                for (out, coef) in out.iter_mut().zip(encoding) {
+                    *out += source * coef;
+                }
+            }
+        }
+
+        rotate(out, self.orientation)
+    }
+}
@@ -0,0 +1,124 @@
+// Renders the reverb for headphones. The early reflections come from a stereo
+// pair in front, and the late tail is spread over virtual sources all around
+// the listener, each with its own decorrelated copy of the tail like the
+// surround outputs. Every source is convolved with the head related impulse
+// responses for its direction, which puts the room outside of the head.
+
+use crate::convolver::{Convolver, BLOCK};
+use crate::hrtf::Hrtf;
+use crate::surround::Decorrelator;
+
+// Azimuth of the left channel of the early reflections in degrees
+const EARLY_AZIMUTH: f32 = 30.0;
+// (azimuth, elevation) of the late tail sources, the ones on the left are fed
+// from the left channel of the tail
+const LATE_DIRECTIONS: [(f32, f32); 6] = [
+    (60.0, 0.0),
+    (-60.0, 0.0),
+    (150.0, 0.0),
+    (-150.0, 0.0),
+    (90.0, 45.0),
+    (-90.0, 45.0),
+];
+// Keeps the tail at the same level as on speakers, three sources per side add
+// up in power
+const LATE_GAIN: f32 = 0.577;
+
+struct Source {
+    // Convolvers for the left and the right ear
+    ears: (Convolver, Convolver),
+    decorrelator: Decorrelator,
+    on_left: bool,
+}
+
+impl Source {
+    fn new(hrtf: &Hrtf, azimuth: f32, elevation: f32, spread: usize) -> Self {
+        // Only the direct form part of the convolver is used, so no FFTs run.
+        // Longer impulse responses are mostly the room the HRTFs were measured in.
+        let (mut left, mut right) = hrtf.interpolate(azimuth, elevation);
+        left.truncate(BLOCK);
+        right.truncate(BLOCK);
+
+        Self {
+            ears: (
+                Convolver::new(left, hrtf.sample_rate),
+                Convolver::new(right, hrtf.sample_rate),
+            ),
+            decorrelator: Decorrelator::new(hrtf.sample_rate as usize, spread),
+            on_left: azimuth > 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> (f32, f32) {
+        (self.ears.0.process(input), self.ears.1.process(input))
+    }
+}
+
+pub struct Binaural {
+    // Either empty or the two early sources followed by the late ones
+    sources: Vec<Source>,
+}
+
+impl Default for Binaural {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Binaural {
+    ///
+    /// Constructor, the output is silent until HRTFs are loaded.
+    pub fn new() -> Self {
+        Self { sources: Vec::new() }
+    }
+
+    /// Sets up the virtual sources, `hrtf` has to be at the current sample rate.
+    /// This allocates, so it must not be called from the audio thread.
+    pub fn load(&mut self, hrtf: &Hrtf) {
+        let early = [(EARLY_AZIMUTH, 0.0), (-EARLY_AZIMUTH, 0.0)];
+        self.sources = early
+            .iter()
+            .chain(LATE_DIRECTIONS.iter())
+            .enumerate()
+            .map(|(spread, &(azimuth, elevation))| Source::new(hrtf, azimuth, elevation, spread))
+            .collect();
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        !self.sources.is_empty()
+    }
+
+    /// Clears the state of the sources, the HRTFs are kept
+    pub fn reset(&mut self) {
+        for source in self.sources.iter_mut() {
+            source.ears.0.reset();
+            source.ears.1.reset();
+            source.decorrelator.reset();
+        }
+    }
+
+    /// Renders the stereo `early` reflections and `late` tail, returns the
+    /// signal for the left and the right ear.
+    pub fn process(&mut self, early: (f32, f32), late: (f32, f32)) -> (f32, f32) {
+        let mut out = (0.0, 0.0);
+        let Some((early_sources, late_sources)) = self.sources.split_first_chunk_mut::<2>() else {
+            return out;
+        };
+
+        for (source, input) in early_sources.iter_mut().zip([early.0, early.1]) {
+            let ears = source.process(input);
+            out.0 += ears.0;
+            out.1 += ears.1;
+        }
+
+        for source in late_sources.iter_mut() {
+            let input = if source.on_left { late.0 } else { late.1 };
+            let input = source.decorrelator.process(input) * LATE_GAIN;
+            let ears = source.process(input);
+            out.0 += ears.0;
+            out.1 += ears.1;
+        }
+
+        out
+    }
+}
@@ -0,0 +1,65 @@
+// A biquad filter in transposed direct form II, with the lowpass coefficients
+// from the RBJ audio EQ cookbook.
+
+use std::f32::consts::PI;
+
+/// Q values of two cascaded biquads that make up a 4th order Butterworth lowpass
+pub const BUTTERWORTH_Q: [f32; 2] = [0.5412, 1.3066];
+
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Biquad {
+    /// Creates a filter that lets everything through.
+    pub fn new() -> Self {
+        Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Turns the filter into a lowpass at `cutoff` cycles per sample, that is
+    /// the cutoff in Hz divided by the sample rate.
+    pub fn set_lowpass(&mut self, cutoff: f32, q: f32) {
+        let w = 2.0 * PI * cutoff.clamp(1e-4, 0.49);
+        let (sin, cos) = w.sin_cos();
+        let alpha = sin / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        self.b1 = (1.0 - cos) / a0;
+        self.b0 = self.b1 * 0.5;
+        self.b2 = self.b0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = input * self.b0 + self.z1;
+        self.z1 = input * self.b1 - output * self.a1 + self.z2;
+        self.z2 = input * self.b2 - output * self.a2;
+        output
+    }
+}
@@ -1,17 +1,86 @@
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::random::Random;
+
+// Compression of the mu-law and A-law curves, the values from G.711
+const MU: f32 = 255.0;
+const A: f32 = 87.6;
+// Range in dB that the logarithmic curve covers, anything below is silent
+const LOG_RANGE: f32 = 96.0;
+// The vintage converter has a fixed word length and switches its input gain up
+// in 6 dB steps for quiet signals, like the gain ranging converters of the 80s
+const VINTAGE_BITS: f32 = 12.0;
+const VINTAGE_RANGES: f32 = 3.0;
+
+/// How the signal is reduced to fewer levels.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Quantizer {
+    /// Evenly spaced levels with zero as one of them, so silence stays silent
+    Linear,
+    /// Companded with the mu-law curve, fine steps for quiet signals
+    MuLaw,
+    /// Companded with the A-law curve, like mu-law with a linear part at the bottom
+    ALaw,
+    /// Levels evenly spaced in dB
+    Log,
+    /// 12 bit with gain ranging, the bit rate is ignored
+    Vintage,
+}
+
+/// The dither added before the bit reduction.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Dither {
+    None,
+    /// Triangular noise of one step, which removes the distortion of the
+    /// quantization and leaves a constant hiss
+    Tpdf,
+    /// TPDF dither with the quantization error fed back, which pushes the
+    /// hiss up to the high end
+    NoiseShaped,
+}
+
 pub struct BitCrusher {
     bit_rate: f32, 
+    // The downsampling factor, every sample is held for this many samples
     sample_rate: f32, 
+    // Samples left until the next one is taken, carried over between blocks
+    // so the fractional part of the factor adds up
+    phase: f32,
     prev_sample: f32, 
     noise_level: f32, 
+    dither: Dither,
+    quantizer: Quantizer,
+    // Quantization error of the last sample, for the noise shaping
+    error: f32,
+    // Gain of the vintage converter for the current sample
+    range_gain: f32,
+    // Round toward zero instead of to the nearest level
+    truncate: bool,
+    random: Random,
+    // Filters out what would alias when downsampling
+    anti_alias: bool,
+    filters: (Biquad, Biquad),
 }
 
 impl BitCrusher {
-    pub fn new() -> Self {
+    ///
+    /// Constructor
+    /// * `seed` - seeds the dither and noise, crushers that run side by side
+    ///   need different seeds or their noise is the same
+    pub fn new(seed: u32) -> Self {
         Self {
             bit_rate: 6.0, 
             sample_rate: 1.0, 
+            phase: 0.0,
             prev_sample: 0.0, 
             noise_level: 0.0, 
+            dither: Dither::None,
+            quantizer: Quantizer::Linear,
+            error: 0.0,
+            range_gain: 1.0,
+            truncate: false,
+            random: Random::new(seed),
+            anti_alias: false,
+            filters: (Biquad::new(), Biquad::new()),
         }
     }
 
@@ -19,31 +88,197 @@ impl BitCrusher {
         self.bit_rate = x;
     }
 
+    /// Sets the downsampling factor, 1.0 keeps the full sample rate. The factor
+    /// doesn't have to be a whole number.
     pub fn set_sample_rate(&mut self, x: f32) {
-        self.sample_rate = x;
+        let x = x.max(1.0);
+        if x != self.sample_rate {
+            self.sample_rate = x;
+            // Keep a little room below the new Nyquist frequency
+            let cutoff = 0.45 / x;
+            self.filters.0.set_lowpass(cutoff, BUTTERWORTH_Q[0]);
+            self.filters.1.set_lowpass(cutoff, BUTTERWORTH_Q[1]);
+        }
     }
 
+    /// Sets the level of the noise added before the bit reduction, as linear gain
     pub fn set_noise_level(&mut self, x: f32) {
         self.noise_level = x;
     }
 
-    pub fn process(&mut self, x: f32, 
-                buffer_index: usize, 
-                buffer_size: usize) -> f32 {
-        let bit = 2.0_f32.powf(self.bit_rate);
-        let sample_scaled = bit * (0.5 * x + 0.5);
-        let sample_rounded = sample_scaled.floor();
-        let mut sample_rescaled = 2.0 * (sample_rounded / bit) - 1.0;
-        
-        let idx = buffer_index % self.sample_rate as usize;
-
-        if self.sample_rate > 1.0 {
-            if idx > 0 && idx < buffer_size {
-                sample_rescaled = self.prev_sample;
-            } 
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    pub fn set_quantizer(&mut self, quantizer: Quantizer) {
+        self.quantizer = quantizer;
+    }
+
+    /// Turns the lowpass filter before the downsampling on or off
+    pub fn set_anti_alias(&mut self, anti_alias: bool) {
+        self.anti_alias = anti_alias;
+    }
+
+    /// Makes the quantizer round toward zero instead of to the nearest level.
+    /// In a feedback loop rounding can keep lifting a decaying signal back up
+    /// to the same step, so it rings forever, while truncating only ever makes
+    /// it smaller.
+    pub fn set_truncate(&mut self, truncate: bool) {
+        self.truncate = truncate;
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.prev_sample = 0.0;
+        self.error = 0.0;
+        self.filters.0.reset();
+        self.filters.1.reset();
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let mut x = x;
+        if self.anti_alias && self.sample_rate > 1.0 {
+            x = self.filters.1.process(self.filters.0.process(x));
+        }
+
+        // Hold the last sample until it is time for a new one
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
         } else {
-            self.prev_sample = sample_rescaled;
+            self.phase += self.sample_rate - 1.0;
+            self.prev_sample = self.convert(x);
         }
-        sample_rescaled
+
+        self.prev_sample
     }
-}
\ No newline at end of file
+
+    /// Adds the noise and the dither, and reduces `x` to the levels of the quantizer.
+    fn convert(&mut self, x: f32) -> f32 {
+        let mut x = x;
+        if self.noise_level > 0.0 {
+            x += (self.random.next_f32() * 2.0 - 1.0) * self.noise_level;
+        }
+
+        let bits = if self.quantizer == Quantizer::Vintage { VINTAGE_BITS } else { self.bit_rate };
+        let step = 2.0 / 2.0_f32.powf(bits);
+
+        // The dither and the noise shaping work on the companded signal, where
+        // the steps are even
+        let y = self.compress(x);
+        let tpdf = (self.random.next_f32() - self.random.next_f32()) * step;
+        let y = match self.dither {
+            Dither::None => y,
+            Dither::Tpdf => y + tpdf,
+            Dither::NoiseShaped => y - self.error + tpdf,
+        };
+
+        let quantized = if self.truncate {
+            (y / step).trunc() * step
+        } else {
+            (y / step).round() * step
+        };
+
+        if self.dither == Dither::NoiseShaped {
+            // The error without the dither, so only the quantization is shaped
+            self.error = quantized - (y - tpdf);
+        }
+
+        self.expand(quantized)
+    }
+
+    /// Maps `x` to the domain the quantizer has even steps in.
+    fn compress(&mut self, x: f32) -> f32 {
+        let sign = x.signum();
+        let x_abs = x.abs();
+
+        match self.quantizer {
+            Quantizer::Linear => x,
+            Quantizer::MuLaw => sign * (1.0 + MU * x_abs).ln() / (1.0 + MU).ln(),
+            Quantizer::ALaw => {
+                let y = if x_abs < 1.0 / A { A * x_abs } else { 1.0 + (A * x_abs).ln() };
+                sign * y / (1.0 + A.ln())
+            }
+            Quantizer::Log => sign * (1.0 + 20.0 * x_abs.log10() / LOG_RANGE).max(0.0),
+            Quantizer::Vintage => {
+                // The most gain that doesn't clip
+                let ranges = (-x_abs.log2()).floor().clamp(0.0, VINTAGE_RANGES);
+                self.range_gain = 2.0_f32.powi(ranges as i32);
+                x * self.range_gain
+            }
+        }
+    }
+
+    /// The inverse of `compress()`.
+    fn expand(&self, y: f32) -> f32 {
+        let sign = y.signum();
+        let y_abs = y.abs();
+
+        match self.quantizer {
+            Quantizer::Linear => y,
+            Quantizer::MuLaw => sign * ((1.0 + MU).powf(y_abs) - 1.0) / MU,
+            Quantizer::ALaw => {
+                let v = y_abs * (1.0 + A.ln());
+                let x_abs = if v < 1.0 { v / A } else { (v - 1.0).exp() / A };
+                sign * x_abs
+            }
+            Quantizer::Log => {
+                if y_abs == 0.0 {
+                    0.0
+                } else {
+                    sign * 10.0_f32.powf((y_abs - 1.0) * LOG_RANGE / 20.0)
+                }
+            }
+            Quantizer::Vintage => y / self.range_gain,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The indices where the crusher took a new sample of a rising ramp, with the
+    /// downsampling set at the start of every block like the plugin does.
+    fn takes(block_size: usize) -> Vec<usize> {
+        let mut crusher = BitCrusher::new(1);
+        crusher.set_bit_rate(16.0);
+        let mut takes = Vec::new();
+        let mut held = f32::NAN;
+
+        for block in 0..4000 / block_size {
+            crusher.set_sample_rate(3.7);
+            for i in 0..block_size {
+                let n = block * block_size + i;
+                crusher.process(n as f32 * 2e-4);
+                if crusher.prev_sample != held {
+                    held = crusher.prev_sample;
+                    takes.push(n);
+                }
+            }
+        }
+        takes
+    }
+
+    #[test]
+    fn hold_continues_across_blocks() {
+        // 3.7 doesn't divide the block size, so a hold that restarted at the
+        // start of a block would take samples early there
+        let takes = takes(100);
+        for gap in takes.windows(2).map(|w| w[1] - w[0]) {
+            assert!(gap == 3 || gap == 4, "gap of {}", gap);
+        }
+        assert_eq!(takes.len(), (4000.0_f32 / 3.7).ceil() as usize);
+        assert_eq!(takes, self::takes(4000));
+    }
+
+    #[test]
+    fn linear_rounds_to_the_nearest_level() {
+        // At 4 bits the levels are 1/8 apart, with zero as one of them
+        let mut crusher = BitCrusher::new(1);
+        crusher.set_bit_rate(4.0);
+        for (x, level) in [(0.0, 0.0), (0.05, 0.0), (0.07, 0.125), (0.3, 0.25)] {
+            assert_eq!(crusher.process(x), level);
+            assert_eq!(crusher.process(-x), -level);
+        }
+    }
+}
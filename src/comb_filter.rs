@@ -1,4 +1,9 @@
+use crate::bitcrusher::BitCrusher;
 use crate::delay_buffer::DelayBuffer;
+use crate::frequency_shifter::FrequencyShifter;
+use crate::oversampler::Oversampler;
+use crate::safety::Peak;
+use crate::saturator::Saturator;
 
 pub struct Comb {
     delay_buffer: DelayBuffer, 
@@ -8,10 +13,23 @@ pub struct Comb {
     filter_state: f32, 
     damp: f32, 
     damp_inv: f32, 
+    shifter: FrequencyShifter,
+    shift: f32,
+    saturator: Saturator,
+    crusher: BitCrusher,
+    oversampler: Oversampler,
+    // How much of the crushed signal is fed back, 0.0 keeps the loop clean
+    crush_mix: f32,
+    // The peak level written to the delay line since the last check
+    peak: Peak,
 }
 
 impl Comb {
-    pub fn new(delay_length: usize) -> Self {
+    /// `seed` seeds the noise of the bit crusher in the feedback path.
+    pub fn new(delay_length: usize, seed: u32) -> Self {
+        let mut crusher = BitCrusher::new(seed);
+        crusher.set_truncate(true);
+
         Self {
             delay_buffer: DelayBuffer::new(delay_length), 
             buffer_len: delay_length, 
@@ -20,6 +38,13 @@ impl Comb {
             filter_state: 0.0, 
             damp: 0.5, 
             damp_inv: 0.5,
+            shifter: FrequencyShifter::new(),
+            shift: 0.0,
+            saturator: Saturator::new(),
+            crusher,
+            oversampler: Oversampler::new(),
+            crush_mix: 0.0,
+            peak: Peak::new(),
         }
     }
 
@@ -37,6 +62,57 @@ impl Comb {
         self.feedback = value;
     }
 
+    /// Sets the frequency shift applied on every pass through the feedback
+    /// path, in cycles per sample. 0.0 turns the shifter off.
+    pub fn set_shift(&mut self, value: f32) {
+        self.shift = value;
+        self.shifter.set_shift(value);
+    }
+
+    /// Sets how much of the feedback goes through the bit crusher, 0.0 turns
+    /// the crusher off.
+    pub fn set_crush_mix(&mut self, value: f32) {
+        // The oversampler doesn't run while the crusher is off, so it would
+        // start with whatever it held when it was turned off
+        if self.crush_mix == 0.0 && value > 0.0 {
+            self.oversampler.reset();
+            self.crusher.reset();
+        }
+        self.crush_mix = value;
+    }
+
+    /// The saturation in the feedback path
+    pub fn saturator(&mut self) -> &mut Saturator {
+        &mut self.saturator
+    }
+
+    /// The bit crusher in the feedback path
+    pub fn crusher(&mut self) -> &mut BitCrusher {
+        &mut self.crusher
+    }
+
+    /// The oversampler around the bit crusher, its latency is taken off the
+    /// delay so the comb stays in tune
+    pub fn oversampler(&mut self) -> &mut Oversampler {
+        &mut self.oversampler
+    }
+
+    pub fn reset(&mut self) {
+        self.delay_buffer.reset();
+        self.filter_state = 0.0;
+        self.shifter.reset();
+        self.saturator.reset();
+        self.crusher.reset();
+        self.oversampler.reset();
+        self.peak = Peak::new();
+    }
+
+    /// Returns false when a NaN, an infinity or a runaway level went into the
+    /// delay line since the last call
+    pub fn is_stable(&mut self) -> bool {
+        self.peak.check()
+    }
+
     pub fn process(&mut self, input: f32) -> f32 {
         // get the current output form the delay buffer
         let output = self.delay_buffer.read();
@@ -47,14 +123,35 @@ impl Comb {
         self.filter_state = output * self.damp_inv + self.filter_state * self.damp;
         
         // process the feedback signal separately.
-        let feedback_out = self.filter_state * self.feedback;
+        let mut feedback_out = self.filter_state * self.feedback;
+
+        // saturate the recirculating signal, loud tails are squashed on every
+        // pass instead of building up
+        if self.saturator.is_active() {
+            feedback_out = self.saturator.process(feedback_out);
+        }
+
+        // shift the recirculating signal up or down a little on every pass
+        if self.shift != 0.0 {
+            feedback_out = self.shifter.process(feedback_out);
+        }
+
+        // crush the recirculating signal, so the damage builds up on every pass
+        let mut latency = 0;
+        if self.crush_mix > 0.0 {
+            let (crusher, mix) = (&mut self.crusher, self.crush_mix);
+            feedback_out = self.oversampler.process(feedback_out, |x| x + (crusher.process(x) - x) * mix);
+            latency = self.oversampler.latency();
+        }
 
         // write the current input and the current filtered feedback signal to 
         // the delay buffer 
-        self.delay_buffer.write(input + feedback_out);
+        let value = input + feedback_out;
+        self.peak.process(value);
+        self.delay_buffer.write(value);
         
         // get the delay length as percentage of the buffer length
-        let d_len = self.buffer_len * self.delay / 100;
+        let d_len = (self.buffer_len * self.delay / 100).saturating_sub(latency).max(1);
         
         // advance the buffer index up to buffer length
         self.delay_buffer.advance_to(d_len - 1);
@@ -0,0 +1,108 @@
+// A compressor for the wet signal, to glue the tail together. Both channels are
+// compressed by the same amount from the louder one, so the stereo image stays
+// put. The gain computer has a soft knee, which is the quadratic from
+// Giannoulis, Massberg and Reiss.
+
+pub struct Compressor {
+    sample_rate: f32,
+    threshold: f32,
+    ratio: f32,
+    knee: f32,
+    // Attack and release times in ms, the coefficients are only worked out
+    // again when these change
+    attack: f32,
+    release: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    // The gain reduction in dB, following the target with the attack and
+    // release times
+    reduction: f32,
+}
+
+impl Compressor {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let mut compressor = Self {
+            sample_rate: sample_rate as f32,
+            threshold: -18.0,
+            ratio: 4.0,
+            knee: 6.0,
+            attack: 0.0,
+            release: 0.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            reduction: 0.0,
+        };
+
+        compressor.set_attack(10.0);
+        compressor.set_release(150.0);
+        compressor
+    }
+
+    /// Sets the threshold in dB
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Sets the ratio, 1.0 leaves the signal alone
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    /// Sets the width of the knee in dB, 0.0 is a hard knee
+    pub fn set_knee(&mut self, knee: f32) {
+        self.knee = knee.max(0.0);
+    }
+
+    /// Sets the attack time in ms
+    pub fn set_attack(&mut self, attack: f32) {
+        if attack != self.attack {
+            self.attack = attack;
+            self.attack_coef = (-1.0 / (attack.max(0.01) * 0.001 * self.sample_rate)).exp();
+        }
+    }
+
+    /// Sets the release time in ms
+    pub fn set_release(&mut self, release: f32) {
+        if release != self.release {
+            self.release = release;
+            self.release_coef = (-1.0 / (release.max(0.01) * 0.001 * self.sample_rate)).exp();
+        }
+    }
+
+    /// Returns the current gain reduction in dB
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+
+    pub fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+
+    /// Returns the compressed `input`.
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let level = 20.0 * input.0.abs().max(input.1.abs()).max(1e-6).log10();
+        let over = level - self.threshold;
+        let slope = 1.0 - 1.0 / self.ratio;
+
+        let target = if 2.0 * over <= -self.knee {
+            0.0
+        } else if 2.0 * over.abs() < self.knee {
+            slope * (over + self.knee / 2.0).powi(2) / (2.0 * self.knee)
+        } else {
+            slope * over
+        };
+
+        let coef = if target > self.reduction {
+            self.attack_coef
+        } else {
+            self.release_coef
+        };
+        self.reduction = target + (self.reduction - target) * coef;
+
+        let gain = 10.0_f32.powf(-self.reduction / 20.0);
+        (input.0 * gain, input.1 * gain)
+    }
+}
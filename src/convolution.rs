@@ -0,0 +1,117 @@
+// Convolution reverb using an impulse response loaded from a WAV file. Mono and
+// stereo files are applied per channel, four channel files are treated as true
+// stereo in the order L->L, L->R, R->L, R->R.
+
+use crate::convolver::Convolver;
+use crate::wav::Wav;
+
+pub struct ConvolutionReverb {
+    sample_rate: f32,
+    // Either empty, two convolvers (left, right) or four for true stereo
+    convolvers: Vec<Convolver>,
+    wet_gains: (f32, f32),
+    wet: f32,
+    width: f32,
+}
+
+impl ConvolutionReverb {
+    ///
+    /// Constructor, the reverb stays silent until an impulse response is loaded.
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let mut convolution = ConvolutionReverb {
+            sample_rate: sample_rate as f32,
+            convolvers: Vec::new(),
+            wet_gains: (0.0, 0.0),
+            wet: 0.8,
+            width: 1.0,
+        };
+
+        convolution.update_wet_gains();
+        convolution
+    }
+
+    /// Loads the impulse response, `wav` has to be at the current sample rate.
+    /// This allocates, so it must not be called from the audio thread.
+    pub fn load(&mut self, wav: &Wav) -> Result<(), &'static str> {
+        let channels = &wav.channels;
+        let layout: &[usize] = match channels.len() {
+            1 => &[0, 0],
+            2 => &[0, 1],
+            4 => &[0, 1, 2, 3],
+            _ => return Err("Only mono, stereo and true stereo impulse responses are supported"),
+        };
+
+        self.convolvers = layout
+            .iter()
+            .map(|&c| Convolver::new(channels[c].clone(), self.sample_rate))
+            .collect();
+        Ok(())
+    }
+
+    /// Returns the length of the loaded impulse response in samples, or 0.
+    pub fn len(&self) -> usize {
+        self.convolvers.first().map_or(0, |c| c.len())
+    }
+
+    /// Returns true when no impulse response is loaded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how long the tail lasts in seconds
+    pub fn tail_time(&self) -> f32 {
+        self.len() as f32 / self.sample_rate
+    }
+
+    /// Clears the state of the convolvers, the impulse response is kept
+    pub fn reset(&mut self) {
+        for convolver in self.convolvers.iter_mut() {
+            convolver.reset();
+        }
+    }
+
+    /// Processes the `input`, returns the reverb signal without the dry signal.
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let out = match self.convolvers.as_mut_slice() {
+            [left, right] => (left.process(input.0), right.process(input.1)),
+            [ll, lr, rl, rr] => (
+                ll.process(input.0) + rl.process(input.1),
+                lr.process(input.0) + rr.process(input.1),
+            ),
+            _ => (0.0, 0.0),
+        };
+
+        (
+            out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1,
+            out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1,
+        )
+    }
+
+    /// Sets the wet signal level
+    pub fn wet(&mut self, wet: f32) {
+        self.wet = wet;
+        self.update_wet_gains();
+    }
+
+    /// Updates the level of the wet and crossfeed signals
+    fn update_wet_gains(&mut self) {
+        self.wet_gains = (
+            self.wet * (self.width / 2.0 + 0.5),
+            self.wet * ((1.0 - self.width) / 2.0),
+        )
+    }
+
+    /// Sets the stereo width
+    pub fn width(&mut self, width: f32) {
+        self.width = width;
+        self.update_wet_gains();
+    }
+
+    /// Sets the decay envelope and the damping applied to the impulse response
+    pub fn shape(&mut self, decay: f32, damp: f32) {
+        for convolver in self.convolvers.iter_mut() {
+            convolver.set_shape(decay, damp);
+        }
+    }
+}
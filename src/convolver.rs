@@ -0,0 +1,329 @@
+// A zero latency, uniformly partitioned FFT convolver for a single channel.
+//
+// The first `BLOCK` samples of the impulse response are applied with a direct
+// form FIR filter so there is no latency. The rest of the impulse response is
+// split into partitions of `BLOCK` samples that are applied in the frequency
+// domain with overlap-save, using a frequency domain delay line. Impulse
+// responses that fit in the first block don't run any FFTs at all.
+
+use crate::fft::{Complex, Fft};
+use crate::one_pole::OnePole;
+use crate::velvet::decay_time;
+use std::f32::consts::PI;
+
+pub const BLOCK: usize = 256;
+
+// How many partitions are rebuilt per block after the shape changed
+const REBUILD_STEP: usize = 8;
+
+/// Everything that is applied to the impulse response before it is used.
+#[derive(Clone, Copy, PartialEq)]
+struct Shape {
+    decay: f32,
+    damp: f32,
+    // The impulse response is faded out over `fade` samples, ending at `end`
+    end: usize,
+    fade: usize,
+}
+
+pub struct Convolver {
+    fft: Fft,
+    sample_rate: f32,
+    // The unprocessed impulse response
+    ir: Vec<f32>,
+    // Number of frequency domain partitions, zero when the head is all there is
+    num_partitions: usize,
+    // Direct form taps for the first block, at most `BLOCK`
+    head: Vec<f32>,
+    // Spectra of the remaining partitions, `2 * BLOCK` bins each
+    partitions: Vec<Complex>,
+    // Spectra of past input blocks, newest first starting at `fdl_pos`
+    fdl: Vec<Complex>,
+    fdl_pos: usize,
+    // The previous and the current input block
+    input: Vec<f32>,
+    // Output of the partitioned part for the current block
+    tail_out: Vec<f32>,
+    scratch: Vec<Complex>,
+    accum: Vec<Complex>,
+    pos: usize,
+    // Shape the partitions currently have, and the one they should have
+    shape: Shape,
+    target_shape: Shape,
+    // The next set of partitions is built in the background over several blocks
+    pending_head: Vec<f32>,
+    pending: Vec<Complex>,
+    rebuild: Option<usize>,
+    rebuild_filter: OnePole,
+}
+
+impl Convolver {
+    ///
+    /// Constructor
+    /// * `ir` - the impulse response, already at `sample_rate`
+    /// * `sample_rate` - the current sample rate
+    pub fn new(ir: Vec<f32>, sample_rate: f32) -> Self {
+        let size = 2 * BLOCK;
+        let num_partitions = ir.len().saturating_sub(BLOCK).div_ceil(BLOCK);
+        let head_len = ir.len().min(BLOCK);
+
+        let mut convolver = Self {
+            fft: Fft::new(size),
+            sample_rate,
+            ir,
+            num_partitions,
+            head: vec![0.0; head_len],
+            partitions: vec![Complex::default(); num_partitions * size],
+            fdl: vec![Complex::default(); num_partitions * size],
+            fdl_pos: 0,
+            input: vec![0.0; size],
+            tail_out: vec![0.0; BLOCK],
+            scratch: vec![Complex::default(); size],
+            accum: vec![Complex::default(); size],
+            pos: 0,
+            shape: Shape { decay: 1.0, damp: 0.0, end: usize::MAX, fade: 0 },
+            target_shape: Shape { decay: 1.0, damp: 0.0, end: usize::MAX, fade: 0 },
+            pending_head: vec![0.0; head_len],
+            pending: vec![Complex::default(); num_partitions * size],
+            rebuild: None,
+            rebuild_filter: OnePole::new(),
+        };
+
+        // Build everything right away, there is no audio running yet
+        convolver.start_rebuild();
+        while convolver.rebuild.is_some() {
+            convolver.rebuild_step();
+        }
+        convolver
+    }
+
+    /// Returns the length of the impulse response in samples.
+    pub fn len(&self) -> usize {
+        self.ir.len()
+    }
+
+    /// Returns true when the impulse response has no samples.
+    pub fn is_empty(&self) -> bool {
+        self.ir.is_empty()
+    }
+
+    /// Sets the decay envelope and damping that are applied to the impulse
+    /// response. The change is picked up gradually over the next blocks.
+    pub fn set_shape(&mut self, decay: f32, damp: f32) {
+        self.target_shape.decay = decay;
+        self.target_shape.damp = damp;
+    }
+
+    /// Truncates the impulse response at `end` samples with a raised cosine
+    /// fade out of `fade` samples. Like `set_shape()` this is picked up gradually.
+    pub fn set_window(&mut self, end: usize, fade: usize) {
+        self.target_shape.end = end;
+        self.target_shape.fade = fade.min(end);
+    }
+
+    /// Clears the input history, the impulse response is kept.
+    pub fn reset(&mut self) {
+        self.fdl.fill(Complex::default());
+        self.input.fill(0.0);
+        self.tail_out.fill(0.0);
+        self.pos = 0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.input[BLOCK + self.pos] = input;
+
+        // Direct form part
+        let mut output = self.tail_out[self.pos];
+        for (j, h) in self.head.iter().enumerate() {
+            output += h * self.input[BLOCK + self.pos - j];
+        }
+
+        self.pos += 1;
+        if self.pos == BLOCK {
+            self.pos = 0;
+            self.process_block();
+        }
+
+        output
+    }
+
+    /// Runs the frequency domain part once a full input block is available.
+    fn process_block(&mut self) {
+        if self.num_partitions > 0 {
+            self.process_partitions();
+        }
+
+        self.input.copy_within(BLOCK.., 0);
+
+        if self.rebuild.is_none() && self.target_shape != self.shape {
+            self.start_rebuild();
+        }
+        if self.rebuild.is_some() {
+            for _ in 0..REBUILD_STEP {
+                self.rebuild_step();
+            }
+        }
+    }
+
+    /// Convolves the last two input blocks with every partition.
+    fn process_partitions(&mut self) {
+        let size = 2 * BLOCK;
+
+        // Transform the last two input blocks and store them as the newest
+        // entry of the delay line
+        for (bin, x) in self.scratch.iter_mut().zip(self.input.iter()) {
+            *bin = Complex::new(*x, 0.0);
+        }
+        self.fft.forward(&mut self.scratch);
+        self.fdl_pos = (self.fdl_pos + self.num_partitions - 1) % self.num_partitions;
+        self.fdl[self.fdl_pos * size..(self.fdl_pos + 1) * size].copy_from_slice(&self.scratch);
+
+        // Multiply and accumulate every partition with its input block
+        self.accum.fill(Complex::default());
+        for k in 0..self.num_partitions {
+            let slot = (self.fdl_pos + k) % self.num_partitions;
+            let x = &self.fdl[slot * size..(slot + 1) * size];
+            let h = &self.partitions[k * size..(k + 1) * size];
+            for ((acc, x), h) in self.accum.iter_mut().zip(x).zip(h) {
+                let y = *x * *h;
+                acc.re += y.re;
+                acc.im += y.im;
+            }
+        }
+        self.fft.inverse(&mut self.accum);
+
+        // Overlap-save: only the second half is valid
+        for (out, y) in self.tail_out.iter_mut().zip(self.accum[BLOCK..].iter()) {
+            *out = y.re;
+        }
+    }
+
+    fn start_rebuild(&mut self) {
+        self.shape = self.target_shape;
+        self.rebuild_filter = OnePole::new();
+        self.rebuild_filter.set_damp(self.shape.damp * 0.9);
+        self.rebuild = Some(0);
+    }
+
+    /// Builds a single partition with the decay envelope and damping applied.
+    /// Partition 0 is the direct form head.
+    fn rebuild_step(&mut self) {
+        let Some(p) = self.rebuild else {
+            return;
+        };
+
+        let size = 2 * BLOCK;
+        let Shape { decay, end, fade, .. } = self.shape;
+        let rt60 = decay_time(decay) * self.sample_rate;
+        let fade_start = end - fade;
+
+        // Fill the first half of the scratch buffer with the shaped segment and
+        // zero pad the rest
+        self.scratch.fill(Complex::default());
+        for j in 0..BLOCK {
+            let n = p * BLOCK + j;
+            let x = self.ir.get(n).copied().unwrap_or(0.0);
+            // A decay of 100% leaves the impulse response untouched
+            let envelope = if decay >= 1.0 {
+                1.0
+            } else {
+                10.0_f32.powf(-3.0 * n as f32 / rt60)
+            };
+            let window = if n < fade_start {
+                1.0
+            } else if n < end {
+                0.5 + 0.5 * (PI * (n - fade_start) as f32 / fade as f32).cos()
+            } else {
+                0.0
+            };
+            self.scratch[j].re = self.rebuild_filter.process(x * envelope) * window;
+        }
+
+        if p == 0 {
+            for (h, x) in self.pending_head.iter_mut().zip(self.scratch.iter()) {
+                *h = x.re;
+            }
+        } else {
+            self.fft.forward(&mut self.scratch);
+            self.pending[(p - 1) * size..p * size].copy_from_slice(&self.scratch);
+        }
+
+        if p < self.num_partitions {
+            self.rebuild = Some(p + 1);
+        } else {
+            std::mem::swap(&mut self.head, &mut self.pending_head);
+            std::mem::swap(&mut self.partitions, &mut self.pending);
+            self.rebuild = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    /// An impulse response over several partitions that doesn't end on one
+    fn ir(len: usize) -> Vec<f32> {
+        let mut random = Random::new(7);
+        (0..len).map(|_| random.next_f32() - 0.5).collect()
+    }
+
+    #[test]
+    fn impulse_gives_the_impulse_response() {
+        let ir = ir(3 * BLOCK + 37);
+        let mut convolver = Convolver::new(ir.clone(), 48000.0);
+
+        let output: Vec<f32> = (0..ir.len() + BLOCK)
+            .map(|n| convolver.process(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        for (n, (y, h)) in output.iter().zip(ir.iter().chain(std::iter::repeat(&0.0))).enumerate() {
+            assert!((y - h).abs() < 1e-4, "sample {n}: {y} instead of {h}");
+        }
+    }
+
+    #[test]
+    fn matches_direct_convolution() {
+        let ir = ir(2 * BLOCK + 5);
+        let mut convolver = Convolver::new(ir.clone(), 48000.0);
+        let mut random = Random::new(11);
+        let input: Vec<f32> = (0..5 * BLOCK + 100).map(|_| random.next_f32() - 0.5).collect();
+
+        for (n, x) in input.iter().enumerate() {
+            let y = convolver.process(*x);
+            let expected: f32 = ir
+                .iter()
+                .zip(input[..=n].iter().rev())
+                .map(|(h, x)| h * x)
+                .sum();
+            assert!((y - expected).abs() < 1e-3, "sample {n}: {y} instead of {expected}");
+        }
+    }
+
+    #[test]
+    fn head_only_impulse_responses_match_direct_convolution() {
+        let ir = ir(BLOCK);
+        let mut convolver = Convolver::new(ir.clone(), 48000.0);
+        assert_eq!(convolver.num_partitions, 0);
+        let mut random = Random::new(13);
+        let input: Vec<f32> = (0..3 * BLOCK + 100).map(|_| random.next_f32() - 0.5).collect();
+
+        for (n, x) in input.iter().enumerate() {
+            let y = convolver.process(*x);
+            let expected: f32 = ir
+                .iter()
+                .zip(input[..=n].iter().rev())
+                .map(|(h, x)| h * x)
+                .sum();
+            assert!((y - expected).abs() < 1e-4, "sample {n}: {y} instead of {expected}");
+        }
+    }
+
+    #[test]
+    fn short_impulse_responses_have_no_latency() {
+        let mut convolver = Convolver::new(vec![0.5, 0.25], 48000.0);
+        assert_eq!(convolver.process(1.0), 0.5);
+        assert_eq!(convolver.process(0.0), 0.25);
+        assert_eq!(convolver.process(0.0), 0.0);
+    }
+}
@@ -14,6 +14,12 @@ impl DelayBuffer {
         }
     }
 
+    /// Clears the buffer and moves back to the start.
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.index = 0;
+    }
+
     /// Reads audio from the buffer.
     pub fn read(&mut self) -> f32 {
         self.buffer[self.index]
@@ -26,6 +32,15 @@ impl DelayBuffer {
         // self.advance();
     }
 
+    /// Writes `input` and returns what was written `length` samples ago,
+    /// `length` has to be between 1 and `buffer.len()`.
+    pub fn delay(&mut self, input: f32, length: usize) -> f32 {
+        let output = self.read();
+        self.write(input);
+        self.advance_to(length - 1);
+        output
+    }
+
     /// Advances the buffer position up to `buffer.len() - 1`.
     pub fn advance(&mut self) {
         self.advance_to(self.buffer.len() - 1);
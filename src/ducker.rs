@@ -0,0 +1,133 @@
+// Ducks the wet signal while the key signal is loud, so the reverb stays out of
+// the way of the dry signal and blooms in the gaps. With lookahead both the wet
+// and the dry signal are delayed, so the ducking can start before the key hits.
+
+use crate::delay_buffer::DelayBuffer;
+
+/// The longest lookahead in ms
+pub const MAX_LOOKAHEAD: f32 = 20.0;
+
+pub struct Ducker {
+    sample_rate: f32,
+    // Key level in dB, following the key with the attack and release times
+    envelope: f32,
+    attack_coef: f32,
+    release_coef: f32,
+    threshold: f32,
+    amount: f32,
+    // The gain applied to the last wet sample
+    gain: f32,
+    // Delay lines for the wet and the dry signal
+    wet_delay: (DelayBuffer, DelayBuffer),
+    dry_delay: (DelayBuffer, DelayBuffer),
+    lookahead: usize,
+}
+
+impl Ducker {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+        let max_len = (MAX_LOOKAHEAD * 0.001 * sample_rate) as usize + 1;
+
+        let mut ducker = Self {
+            sample_rate,
+            envelope: -120.0,
+            attack_coef: 0.0,
+            release_coef: 0.0,
+            threshold: -30.0,
+            amount: 12.0,
+            gain: 1.0,
+            wet_delay: (DelayBuffer::new(max_len), DelayBuffer::new(max_len)),
+            dry_delay: (DelayBuffer::new(max_len), DelayBuffer::new(max_len)),
+            lookahead: 0,
+        };
+
+        ducker.set_attack(10.0);
+        ducker.set_release(250.0);
+        ducker
+    }
+
+    /// Sets the threshold in dB
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Sets the maximum gain reduction in dB
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount;
+    }
+
+    /// Sets the attack time in ms
+    pub fn set_attack(&mut self, attack: f32) {
+        self.attack_coef = (-1.0 / (attack.max(0.01) * 0.001 * self.sample_rate)).exp();
+    }
+
+    /// Sets the release time in ms
+    pub fn set_release(&mut self, release: f32) {
+        self.release_coef = (-1.0 / (release.max(0.01) * 0.001 * self.sample_rate)).exp();
+    }
+
+    /// Sets the lookahead in ms
+    pub fn set_lookahead(&mut self, lookahead: f32) {
+        self.lookahead = (lookahead.clamp(0.0, MAX_LOOKAHEAD) * 0.001 * self.sample_rate) as usize;
+    }
+
+    /// Returns the lookahead in samples, this is the latency the ducker adds.
+    pub fn latency(&self) -> u32 {
+        self.lookahead as u32
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = -120.0;
+        self.gain = 1.0;
+        self.wet_delay.0.reset();
+        self.wet_delay.1.reset();
+        self.dry_delay.0.reset();
+        self.dry_delay.1.reset();
+    }
+
+    /// Follows the `key` and returns the delayed `wet` signal with ducking
+    /// applied, and the delayed `dry` signal.
+    pub fn process(
+        &mut self,
+        key: f32,
+        wet: (f32, f32),
+        dry: (f32, f32),
+    ) -> ((f32, f32), (f32, f32)) {
+        let level = 20.0 * key.abs().max(1e-6).log10();
+        let coef = if level > self.envelope {
+            self.attack_coef
+        } else {
+            self.release_coef
+        };
+        self.envelope = level + (self.envelope - level) * coef;
+
+        // Everything above the threshold is taken off, up to the amount
+        let reduction = (self.envelope - self.threshold).clamp(0.0, self.amount);
+        self.gain = 10.0_f32.powf(-reduction / 20.0);
+
+        let (wet, dry) = if self.lookahead > 0 {
+            (
+                (
+                    self.wet_delay.0.delay(wet.0, self.lookahead),
+                    self.wet_delay.1.delay(wet.1, self.lookahead),
+                ),
+                (
+                    self.dry_delay.0.delay(dry.0, self.lookahead),
+                    self.dry_delay.1.delay(dry.1, self.lookahead),
+                ),
+            )
+        } else {
+            (wet, dry)
+        };
+
+        ((wet.0 * self.gain, wet.1 * self.gain), dry)
+    }
+
+    /// Returns the gain applied to the last wet sample
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+}
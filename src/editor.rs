@@ -0,0 +1,93 @@
+// The editor. Everything that can't be a parameter lives at the top, the file
+// paths with their load buttons and the gain reduction meters, and below that
+// every parameter gets a generic slider, since hosts stop showing their own
+// generic editor once there is one.
+
+use crate::loader::Loader;
+use crate::{Lashverb, LashverbParams, Task};
+use nih_plug::prelude::*;
+use nih_plug_egui::widgets::generic_ui::{self, GenericSlider};
+use nih_plug_egui::{create_egui_editor, egui, EguiState};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// The most gain reduction the meters show, in dB
+const METER_RANGE: f32 = 24.0;
+
+/// The default size of the editor window
+pub fn default_state() -> Arc<EguiState> {
+    EguiState::from_size(520, 640)
+}
+
+/// What the editor keeps between frames
+struct State {
+    // The paths as they are being typed, only used once they are loaded
+    ir_path: String,
+    hrtf_path: String,
+}
+
+pub fn create(
+    params: Arc<LashverbParams>,
+    loader: Arc<Loader>,
+    async_executor: AsyncExecutor<Lashverb>,
+) -> Option<Box<dyn Editor>> {
+    let ir_path = params.ir_path.read().map(|p| p.clone()).unwrap_or_default();
+    let hrtf_path = params.hrtf_path.read().map(|p| p.clone()).unwrap_or_default();
+
+    create_egui_editor(
+        params.editor_state.clone(),
+        State { ir_path, hrtf_path },
+        |_, _| {},
+        move |egui_ctx, setter, state| {
+            egui::CentralPanel::default().show(egui_ctx, |ui| {
+                ui.label("Impulse response (WAV)");
+                if path_field(ui, &mut state.ir_path, &loader.ir_status()) {
+                    if let Ok(mut path) = params.ir_path.write() {
+                        *path = state.ir_path.trim().to_string();
+                    }
+                    async_executor.execute_background(Task::LoadIr);
+                }
+
+                ui.label("HRTFs for the binaural output (HRIR)");
+                if path_field(ui, &mut state.hrtf_path, &loader.hrtf_status()) {
+                    if let Ok(mut path) = params.hrtf_path.write() {
+                        *path = state.hrtf_path.trim().to_string();
+                    }
+                    async_executor.execute_background(Task::LoadHrtf);
+                }
+
+                ui.separator();
+                meter(ui, "Compressor", &params.compressor_reduction);
+                meter(ui, "Limiter", &params.limiter_reduction);
+
+                ui.separator();
+                generic_ui::create(ui, params.clone(), setter, GenericSlider);
+            });
+        },
+    )
+}
+
+/// A text field for a file path with a load button and what happened to the
+/// last file below it. Returns whether the button was clicked.
+fn path_field(ui: &mut egui::Ui, path: &mut String, status: &str) -> bool {
+    let clicked = ui
+        .horizontal(|ui| {
+            ui.text_edit_singleline(path);
+            ui.button("Load").clicked()
+        })
+        .inner;
+    ui.label(status);
+    clicked
+}
+
+/// A gain reduction meter for a reduction in dB stored as the bits of an f32.
+fn meter(ui: &mut egui::Ui, name: &str, reduction: &AtomicU32) {
+    let reduction = f32::from_bits(reduction.load(Ordering::Relaxed));
+    ui.horizontal(|ui| {
+        ui.label(name);
+        ui.add(
+            egui::ProgressBar::new((reduction / METER_RANGE).clamp(0.0, 1.0))
+                .text(format!("-{:.1} dB", reduction)),
+        );
+    });
+}
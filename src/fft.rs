@@ -0,0 +1,106 @@
+// A small in-place radix-2 FFT. The twiddle factors and the bit reversal table
+// are computed up front so transforming never allocates.
+
+use std::f32::consts::PI;
+use std::ops::Mul;
+
+#[derive(Clone, Copy, Default)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex {
+            re: self.re * other.re - self.im * other.im,
+            im: self.re * other.im + self.im * other.re,
+        }
+    }
+}
+
+pub struct Fft {
+    size: usize,
+    twiddles: Vec<Complex>,
+    reversed: Vec<usize>,
+}
+
+impl Fft {
+    /// Creates a new FFT, `size` has to be a power of two.
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+
+        let bits = size.trailing_zeros();
+        let twiddles = (0..size / 2)
+            .map(|i| {
+                let phase = -2.0 * PI * i as f32 / size as f32;
+                Complex::new(phase.cos(), phase.sin())
+            })
+            .collect();
+        let reversed = (0..size)
+            .map(|i| if bits == 0 { 0 } else { i.reverse_bits() >> (usize::BITS - bits) })
+            .collect();
+
+        Self {
+            size,
+            twiddles,
+            reversed,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Forward transform of `data` in place.
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    /// Inverse transform of `data` in place, including the 1/N scaling.
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+
+        let scale = 1.0 / self.size as f32;
+        for x in data.iter_mut() {
+            x.re *= scale;
+            x.im *= scale;
+        }
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        for i in 0..self.size {
+            let j = self.reversed[i];
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut len = 2;
+        while len <= self.size {
+            let half = len / 2;
+            let step = self.size / len;
+            for start in (0..self.size).step_by(len) {
+                for k in 0..half {
+                    let mut w = self.twiddles[k * step];
+                    if inverse {
+                        w.im = -w.im;
+                    }
+                    let a = data[start + k];
+                    let b = data[start + k + half] * w;
+                    data[start + k] = Complex::new(a.re + b.re, a.im + b.im);
+                    data[start + k + half] = Complex::new(a.re - b.re, a.im - b.im);
+                }
+            }
+            len *= 2;
+        }
+    }
+}
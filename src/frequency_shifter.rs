@@ -0,0 +1,141 @@
+// A single sideband frequency shifter. A pair of allpass chains acts as a
+// Hilbert transformer, giving two outputs that are 90 degrees apart over most
+// of the audio range. Multiplying those with a quadrature oscillator and adding
+// them moves every frequency up or down by the same amount in hertz.
+//
+// The allpass coefficients are Olli Niemitalo's 8th order Hilbert transformer.
+
+use std::f32::consts::PI;
+
+const COEFS_1: [f32; 4] = [0.692_387_8, 0.936_065_4, 0.988_229_5, 0.998_748_8];
+const COEFS_2: [f32; 4] = [0.402_192_1, 0.856_171_1, 0.972_290_9, 0.995_288_5];
+
+// A second order allpass section of the form y = a² (x + y[n-2]) - x[n-2]
+#[derive(Clone, Copy)]
+struct Stage {
+    coef: f32,
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Stage {
+    fn new(a: f32) -> Self {
+        Self {
+            coef: a * a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = self.coef * (input + self.y[1]) - self.x[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+pub struct FrequencyShifter {
+    path_1: [Stage; 4],
+    path_2: [Stage; 4],
+    // The first path is delayed by one extra sample
+    delayed: f32,
+    // Oscillator phase from 0.0 to 1.0 and increment per sample
+    phase: f32,
+    shift: f32,
+}
+
+impl Default for FrequencyShifter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrequencyShifter {
+    pub fn new() -> Self {
+        Self {
+            path_1: COEFS_1.map(Stage::new),
+            path_2: COEFS_2.map(Stage::new),
+            delayed: 0.0,
+            phase: 0.0,
+            shift: 0.0,
+        }
+    }
+
+    /// Sets the shift in cycles per sample, so the shift in hertz divided by the
+    /// sample rate. Negative values shift down.
+    pub fn set_shift(&mut self, shift: f32) {
+        self.shift = shift;
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.path_1.iter_mut().chain(self.path_2.iter_mut()) {
+            stage.x = [0.0; 2];
+            stage.y = [0.0; 2];
+        }
+        self.delayed = 0.0;
+        self.phase = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let mut re = input;
+        for stage in self.path_1.iter_mut() {
+            re = stage.process(re);
+        }
+        let re = std::mem::replace(&mut self.delayed, re);
+
+        let mut im = input;
+        for stage in self.path_2.iter_mut() {
+            im = stage.process(im);
+        }
+
+        self.phase += self.shift;
+        self.phase -= self.phase.floor();
+        let (sin, cos) = (2.0 * PI * self.phase).sin_cos();
+
+        re * cos + im * sin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 44100.0;
+
+    /// The amplitude of `frequency` in `signal`, which has to hold a whole
+    /// number of its cycles
+    fn amplitude(signal: &[f32], frequency: f32) -> f32 {
+        let w = 2.0 * std::f64::consts::PI * frequency as f64 / SAMPLE_RATE as f64;
+        let (re, im) = signal
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, &x)| {
+                let (sin, cos) = (w * n as f64).sin_cos();
+                (re + x as f64 * cos, im + x as f64 * sin)
+            });
+        (2.0 * re.hypot(im) / signal.len() as f64) as f32
+    }
+
+    #[test]
+    fn shifts_a_tone_up_by_the_shift() {
+        let mut shifter = FrequencyShifter::new();
+        shifter.set_shift(100.0 / SAMPLE_RATE);
+        let output: Vec<f32> = (0..2 * SAMPLE_RATE as usize)
+            .map(|n| {
+                let phase = (1000.0 * n as f64 / SAMPLE_RATE as f64).fract();
+                shifter.process((2.0 * std::f64::consts::PI * phase).sin() as f32)
+            })
+            .skip(SAMPLE_RATE as usize)
+            .collect();
+
+        let shifted = amplitude(&output, 1100.0);
+        let mirrored = amplitude(&output, 900.0);
+        assert!((shifted - 1.0).abs() < 0.01, "shifted tone at {shifted}");
+        assert!(
+            20.0 * (shifted / mirrored).log10() > 50.0,
+            "sideband only {} dB down",
+            20.0 * (shifted / mirrored).log10()
+        );
+    }
+}
@@ -0,0 +1,118 @@
+// A gate for the classic gated reverb sound. An envelope follower on the key
+// signal opens the gate, which stays open for the hold time after the key falls
+// below the threshold and then closes over the release time.
+
+// How fast the gate opens, in ms. Just long enough to not click.
+const OPEN_TIME: f32 = 0.5;
+// Release time of the envelope follower, in ms
+const FOLLOWER_RELEASE: f32 = 10.0;
+
+/// The curve the gate follows when it closes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Release {
+    /// Closes within a millisecond, ignoring the release time
+    Hard,
+    /// Fades out in a straight line
+    Linear,
+    /// Falls by 60 dB over the release time
+    Exponential,
+}
+
+pub struct Gate {
+    sample_rate: f32,
+    envelope: f32,
+    follower_coef: f32,
+    threshold: f32,
+    hold: usize,
+    hold_counter: usize,
+    release_time: f32,
+    // Per sample decrement for the linear release and factor for the exponential one
+    release_step: f32,
+    release_coef: f32,
+    release: Release,
+    gain: f32,
+}
+
+impl Gate {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        let mut gate = Self {
+            sample_rate,
+            envelope: 0.0,
+            follower_coef: (-1.0 / (FOLLOWER_RELEASE * 0.001 * sample_rate)).exp(),
+            threshold: 0.1,
+            hold: 0,
+            hold_counter: 0,
+            release_time: 0.0,
+            release_step: 0.0,
+            release_coef: 0.0,
+            release: Release::Hard,
+            gain: 0.0,
+        };
+
+        gate.set_release_time(100.0);
+        gate
+    }
+
+    /// Sets the threshold as linear gain
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Sets the hold time in ms
+    pub fn set_hold(&mut self, hold: f32) {
+        self.hold = (hold * 0.001 * self.sample_rate) as usize;
+    }
+
+    /// Sets the release time in ms
+    pub fn set_release_time(&mut self, release_time: f32) {
+        let release_time = release_time.max(1.0);
+        if release_time != self.release_time {
+            let release_samples = release_time * 0.001 * self.sample_rate;
+            self.release_time = release_time;
+            self.release_step = 1.0 / release_samples;
+            self.release_coef = 10.0_f32.powf(-3.0 / release_samples);
+        }
+    }
+
+    pub fn set_release(&mut self, release: Release) {
+        self.release = release;
+    }
+
+    /// Closes the gate and clears the envelope follower
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+        self.hold_counter = 0;
+        self.gain = 0.0;
+    }
+
+    /// Follows `key` and returns the gain to apply to the gated signal.
+    pub fn process(&mut self, key: f32) -> f32 {
+        self.envelope = key.abs().max(self.envelope * self.follower_coef);
+
+        if self.envelope > self.threshold {
+            self.hold_counter = self.hold;
+            self.gain = (self.gain + 1.0 / (OPEN_TIME * 0.001 * self.sample_rate)).min(1.0);
+        } else if self.hold_counter > 0 {
+            self.hold_counter -= 1;
+            self.gain = (self.gain + 1.0 / (OPEN_TIME * 0.001 * self.sample_rate)).min(1.0);
+        } else {
+            self.gain = match self.release {
+                Release::Hard => self.gain - 1.0 / (0.001 * self.sample_rate),
+                Release::Linear => self.gain - self.release_step,
+                Release::Exponential => {
+                    // Snap to zero once the gain is inaudible
+                    let gain = self.gain * self.release_coef;
+                    if gain < 0.001 { 0.0 } else { gain }
+                }
+            }
+            .max(0.0);
+        }
+
+        self.gain
+    }
+}
@@ -0,0 +1,176 @@
+// Head related impulse responses for the binaural output. SOFA files are HDF5
+// containers, so rather than pulling in an HDF5 reader the measurements are read
+// from a simple format that SOFA files can be converted to. All values are
+// little endian:
+//
+//     b"HRIR"
+//     u32    sample rate
+//     u32    number of directions
+//     u32    length of each impulse response in samples
+//     then for every direction:
+//     f32    azimuth in degrees, counterclockwise from the front
+//     f32    elevation in degrees, up from the horizon
+//     f32 *  length, the left ear impulse response
+//     f32 *  length, the right ear impulse response
+
+use crate::wav::Wav;
+use std::path::Path;
+
+// Number of measured directions that are blended for a direction in between
+const NEIGHBOURS: usize = 3;
+
+pub struct Hrtf {
+    pub sample_rate: f32,
+    /// (azimuth, elevation) of every measurement in degrees
+    pub directions: Vec<(f32, f32)>,
+    /// The left and right ear impulse responses of every measurement
+    pub irs: Vec<(Vec<f32>, Vec<f32>)>,
+}
+
+impl Hrtf {
+    /// Reads and decodes the file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, &'static str> {
+        let bytes = std::fs::read(path).map_err(|_| "Could not read the file")?;
+        Self::parse(&bytes)
+    }
+
+    /// Decodes an HRIR file from memory.
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.starts_with(b"\x89HDF") {
+            return Err("SOFA files have to be converted to the HRIR format first");
+        }
+        if bytes.len() < 16 || &bytes[0..4] != b"HRIR" {
+            return Err("Not an HRIR file");
+        }
+
+        let word = |pos: usize| [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        let sample_rate = u32::from_le_bytes(word(4)) as f32;
+        let count = u32::from_le_bytes(word(8)) as usize;
+        let len = u32::from_le_bytes(word(12)) as usize;
+
+        if sample_rate <= 0.0 || count == 0 || len == 0 {
+            return Err("The HRIR file is empty");
+        }
+        let stride = 4 * (2 + 2 * len);
+        if count.checked_mul(stride).is_none_or(|size| bytes.len() < 16 + size) {
+            return Err("The HRIR file is truncated");
+        }
+
+        let floats = |pos: usize, n: usize| -> Vec<f32> {
+            (0..n).map(|i| f32::from_le_bytes(word(pos + 4 * i))).collect()
+        };
+
+        let mut directions = Vec::with_capacity(count);
+        let mut irs = Vec::with_capacity(count);
+        for d in 0..count {
+            let pos = 16 + d * stride;
+            let angles = floats(pos, 2);
+            directions.push((angles[0], angles[1]));
+            irs.push((floats(pos + 8, len), floats(pos + 8 + 4 * len, len)));
+        }
+
+        Ok(Self { sample_rate, directions, irs })
+    }
+
+    /// Returns a copy with every impulse response resampled to `sample_rate`.
+    pub fn resampled(&self, sample_rate: f32) -> Self {
+        let irs = self.irs
+            .iter()
+            .map(|(left, right)| {
+                let wav = Wav {
+                    sample_rate: self.sample_rate,
+                    channels: vec![left.clone(), right.clone()],
+                }
+                .resampled(sample_rate);
+                let [left, right]: [Vec<f32>; 2] = wav.channels.try_into().unwrap_or_default();
+                (left, right)
+            })
+            .collect();
+
+        Self {
+            sample_rate,
+            directions: self.directions.clone(),
+            irs,
+        }
+    }
+
+    /// Returns the left and right impulse responses for `azimuth` and `elevation`
+    /// in degrees. Directions that were not measured are blended from the
+    /// nearest measurements, weighted by how close they are.
+    pub fn interpolate(&self, azimuth: f32, elevation: f32) -> (Vec<f32>, Vec<f32>) {
+        let target = unit_vector(azimuth, elevation);
+        let mut nearest: Vec<(f32, usize)> = self.directions
+            .iter()
+            .enumerate()
+            .map(|(i, &(az, el))| {
+                let dot: f32 = unit_vector(az, el).iter().zip(&target).map(|(a, b)| a * b).sum();
+                (dot.clamp(-1.0, 1.0).acos(), i)
+            })
+            .collect();
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nearest.truncate(NEIGHBOURS);
+
+        // A measurement right at the direction is used as it is
+        let len = self.irs[0].0.len();
+        if nearest[0].0 < 1e-4 {
+            return self.irs[nearest[0].1].clone();
+        }
+
+        let total: f32 = nearest.iter().map(|(angle, _)| 1.0 / angle).sum();
+        let mut ir = (vec![0.0; len], vec![0.0; len]);
+        for &(angle, i) in nearest.iter() {
+            let weight = 1.0 / (angle * total);
+            for (out, x) in ir.0.iter_mut().zip(&self.irs[i].0) {
+                *out += x * weight;
+            }
+            for (out, x) in ir.1.iter_mut().zip(&self.irs[i].1) {
+                *out += x * weight;
+            }
+        }
+        ir
+    }
+}
+
+fn unit_vector(azimuth: f32, elevation: f32) -> [f32; 3] {
+    let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+    [
+        azimuth.cos() * elevation.cos(),
+        azimuth.sin() * elevation.cos(),
+        elevation.sin(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four directions on the horizon, front, left, back and right, with impulse
+    // responses of four samples. Each ear gets a single impulse, and the front
+    // and the left direction have theirs at different positions in the left ear.
+    const FIXTURE: &[u8] = include_bytes!("../tests/fixtures/horizon.hrir");
+
+    #[test]
+    fn parses_the_fixture() {
+        let hrtf = Hrtf::parse(FIXTURE).unwrap();
+        assert_eq!(hrtf.sample_rate, 48000.0);
+        assert_eq!(hrtf.directions, vec![(0.0, 0.0), (90.0, 0.0), (180.0, 0.0), (-90.0, 0.0)]);
+        assert_eq!(hrtf.irs[1], (vec![1.0, 0.0, 0.0, 0.0], vec![0.0, 0.0, 0.0, 0.5]));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(Hrtf::parse(b"\x89HDF\r\n\x1a\n").is_err());
+        assert!(Hrtf::parse(&FIXTURE[..FIXTURE.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn interpolates_between_directions() {
+        let hrtf = Hrtf::parse(FIXTURE).unwrap();
+        assert_eq!(hrtf.interpolate(90.0, 0.0), hrtf.irs[1]);
+
+        // Halfway between the front and the left both are weighted the same
+        let (left, _) = hrtf.interpolate(45.0, 0.0);
+        assert!((left[0] - left[1]).abs() < 1e-4);
+        assert!(left[0] > left[2]);
+    }
+}
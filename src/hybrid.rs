@@ -0,0 +1,298 @@
+// Hybrid reverb: the first part of a measured impulse response is used for the
+// early reflections and crossfades into the algorithmic comb/allpass tail. The
+// decay and damping of the tail are matched to the late decay of the impulse
+// response, measured with Schroeder backward integration.
+
+use crate::convolver::Convolver;
+use crate::delay_buffer::DelayBuffer;
+use crate::one_pole::OnePole;
+use crate::oversampler;
+use crate::reverb::Reverb;
+use crate::wav::Wav;
+
+/// The longest crossover time in milliseconds
+pub const MAX_CROSSOVER: f32 = 300.0;
+
+// Length of the crossfade between the early part and the tail, in milliseconds
+const FADE: f32 = 20.0;
+
+// The range of the energy decay curve used to estimate the decay time, in dB
+const FIT_START: f32 = -5.0;
+const FIT_END: f32 = -25.0;
+
+pub struct HybridReverb {
+    sample_rate: f32,
+    // Either empty, two convolvers (left, right) or four for true stereo
+    early: Vec<Convolver>,
+    tail: Reverb,
+    // Delays the input of the tail so it builds up around the crossover time
+    predelay: (DelayBuffer, DelayBuffer),
+    predelay_len: usize,
+    // Delays the early part by the latency of the tail
+    early_delay: (DelayBuffer, DelayBuffer),
+    // Late decay time in seconds and damping measured from the impulse response
+    matched: Option<(f32, f32)>,
+    wet_gains: (f32, f32),
+    wet: f32,
+    width: f32,
+    crossover: f32,
+    // The last output of the early part, with the wet gains applied
+    early_out: (f32, f32),
+}
+
+impl HybridReverb {
+    ///
+    /// Constructor, only the tail is heard until an impulse response is loaded.
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let max_len = (MAX_CROSSOVER * 0.001 * sample_rate as f32) as usize + 1;
+
+        let mut hybrid = HybridReverb {
+            sample_rate: sample_rate as f32,
+            early: Vec::new(),
+            tail: Reverb::new(sample_rate),
+            predelay: (DelayBuffer::new(max_len), DelayBuffer::new(max_len)),
+            predelay_len: 0,
+            early_delay: (
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+            ),
+            matched: None,
+            wet_gains: (0.0, 0.0),
+            wet: 0.8,
+            width: 1.0,
+            crossover: 0.0,
+            early_out: (0.0, 0.0),
+        };
+
+        hybrid.update_wet_gains();
+        hybrid.crossover(80.0);
+        hybrid
+    }
+
+    /// Loads the impulse response, `wav` has to be at the current sample rate.
+    /// This allocates, so it must not be called from the audio thread.
+    pub fn load(&mut self, wav: &Wav) -> Result<(), &'static str> {
+        let channels = &wav.channels;
+        let layout: &[usize] = match channels.len() {
+            1 => &[0, 0],
+            2 => &[0, 1],
+            4 => &[0, 1, 2, 3],
+            _ => return Err("Only mono, stereo and true stereo impulse responses are supported"),
+        };
+
+        // Only the part up to the longest crossover time is ever needed
+        let max_len = (MAX_CROSSOVER * 0.001 * self.sample_rate) as usize;
+        self.early = layout
+            .iter()
+            .map(|&c| {
+                let early = channels[c][..channels[c].len().min(max_len)].to_vec();
+                Convolver::new(early, self.sample_rate)
+            })
+            .collect();
+
+        self.matched = measure(channels, self.sample_rate);
+        self.crossover(self.crossover);
+        Ok(())
+    }
+
+    /// Clears the early part, the predelay and the tail
+    pub fn reset(&mut self) {
+        for early in self.early.iter_mut() {
+            early.reset();
+        }
+        self.predelay.0.reset();
+        self.predelay.1.reset();
+        self.early_delay.0.reset();
+        self.early_delay.1.reset();
+        self.tail.reset();
+    }
+
+    /// Processes the `input`, returns the reverb signal without the dry signal.
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let early = match self.early.as_mut_slice() {
+            [left, right] => (left.process(input.0), right.process(input.1)),
+            [ll, lr, rl, rr] => (
+                ll.process(input.0) + rl.process(input.1),
+                lr.process(input.0) + rr.process(input.1),
+            ),
+            _ => (0.0, 0.0),
+        };
+
+        // The oversampling in the tail delays it, keep the early part in line
+        let latency = self.tail.latency();
+        let early = if latency > 0 {
+            (
+                self.early_delay.0.delay(early.0, latency),
+                self.early_delay.1.delay(early.1, latency),
+            )
+        } else {
+            early
+        };
+
+        let delayed = if self.predelay_len > 0 {
+            (
+                self.predelay.0.delay(input.0, self.predelay_len),
+                self.predelay.1.delay(input.1, self.predelay_len),
+            )
+        } else {
+            input
+        };
+
+        self.early_out = (
+            early.0 * self.wet_gains.0 + early.1 * self.wet_gains.1,
+            early.1 * self.wet_gains.0 + early.0 * self.wet_gains.1,
+        );
+
+        // The tail has the wet gains applied already
+        let tail = self.tail.process(delayed);
+
+        (self.early_out.0 + tail.0, self.early_out.1 + tail.1)
+    }
+
+    /// Returns how long the tail lasts in seconds, the early part or the
+    /// delayed algorithmic tail, whichever ends last
+    pub fn tail_time(&self) -> f32 {
+        let early = if self.early.is_empty() { 0.0 } else { self.crossover * 0.001 };
+        early.max(self.predelay_len as f32 / self.sample_rate + self.tail.decay_time())
+    }
+
+    /// Returns the last output of the early part on its own
+    pub fn early_out(&self) -> (f32, f32) {
+        self.early_out
+    }
+
+    /// Gives access to the algorithmic tail for the settings that are not
+    /// matched to the impulse response, like size and the bit crusher.
+    pub fn tail(&mut self) -> &mut Reverb {
+        &mut self.tail
+    }
+
+    /// Sets the time in milliseconds where the early part crossfades into the tail
+    pub fn crossover(&mut self, crossover: f32) {
+        self.crossover = crossover.clamp(0.0, MAX_CROSSOVER);
+
+        let end = (self.crossover * 0.001 * self.sample_rate) as usize;
+        let fade = (FADE * 0.001 * self.sample_rate) as usize;
+        self.predelay_len = end.saturating_sub(fade);
+        for early in self.early.iter_mut() {
+            early.set_window(end, fade);
+        }
+    }
+
+    /// Sets the decay of the tail. Ignored when it is matched to an impulse response.
+    pub fn decay(&mut self, decay: f32) {
+        match self.matched {
+            Some((time, _)) => {
+                let decay = self.tail.decay_for_time(time);
+                self.tail.decay(decay);
+            }
+            None => self.tail.decay(decay),
+        }
+    }
+
+    /// Sets the damping of the tail. Ignored when it is matched to an impulse response.
+    pub fn damp(&mut self, damp: f32) {
+        match self.matched {
+            Some((_, damp)) => self.tail.damp(damp),
+            None => self.tail.damp(damp),
+        }
+    }
+
+    /// Sets the wet signal level
+    pub fn wet(&mut self, wet: f32) {
+        self.wet = wet;
+        self.tail.wet(wet);
+        self.update_wet_gains();
+    }
+
+    /// Updates the level of the wet and crossfeed signals
+    fn update_wet_gains(&mut self) {
+        self.wet_gains = (
+            self.wet * (self.width / 2.0 + 0.5),
+            self.wet * ((1.0 - self.width) / 2.0),
+        )
+    }
+
+    /// Sets the stereo width
+    pub fn width(&mut self, width: f32) {
+        self.width = width;
+        self.tail.width(width);
+        self.update_wet_gains();
+    }
+}
+
+/// Measures the late decay time and a matching damping value. The high band
+/// decaying faster than the low band translates to more damping.
+fn measure(channels: &[Vec<f32>], sample_rate: f32) -> Option<(f32, f32)> {
+    let len = channels.iter().map(|c| c.len()).max().unwrap_or(0);
+    let mut full = vec![0.0; len];
+    let mut low = vec![0.0; len];
+    let mut high = vec![0.0; len];
+
+    for channel in channels {
+        let mut lowpass = OnePole::new();
+        lowpass.set_damp(0.9);
+        for (n, x) in channel.iter().enumerate() {
+            let l = lowpass.process(*x);
+            full[n] += x * x;
+            low[n] += l * l;
+            high[n] += (x - l) * (x - l);
+        }
+    }
+
+    let time = decay_time(&mut full, sample_rate)?;
+    let damp = match (decay_time(&mut low, sample_rate), decay_time(&mut high, sample_rate)) {
+        (Some(low), Some(high)) => (1.0 - high / low).clamp(0.0, 1.0),
+        _ => 0.0,
+    };
+
+    Some((time, damp))
+}
+
+/// Estimates the RT60 in seconds from the per sample `energy`, which is
+/// overwritten with the energy decay curve.
+fn decay_time(energy: &mut [f32], sample_rate: f32) -> Option<f32> {
+    // Schroeder backward integration
+    let mut sum = 0.0_f64;
+    for e in energy.iter_mut().rev() {
+        sum += *e as f64;
+        *e = sum as f32;
+    }
+
+    let total = *energy.first()?;
+    if total <= 0.0 {
+        return None;
+    }
+
+    // Least squares fit of a line through the decay curve in dB
+    let (mut n, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (i, e) in energy.iter().enumerate() {
+        let db = 10.0 * (e / total).max(1e-12).log10();
+        if db > FIT_START {
+            continue;
+        }
+        if db < FIT_END {
+            break;
+        }
+        let x = i as f64;
+        let y = db as f64;
+        n += 1.0;
+        sx += x;
+        sy += y;
+        sxx += x * x;
+        sxy += x * y;
+    }
+
+    let denominator = n * sxx - sx * sx;
+    if n < 2.0 || denominator == 0.0 {
+        return None;
+    }
+
+    let slope = (n * sxy - sx * sy) / denominator;
+    if slope >= 0.0 {
+        return None;
+    }
+
+    Some((-60.0 / (slope * sample_rate as f64)) as f32)
+}
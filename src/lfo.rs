@@ -44,6 +44,10 @@ impl Lfo {
         self.shape = x;
     }
 
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
     pub fn output(&mut self) -> f32 {
         self.phase += 2.0 * PI * self.speed / self.sample_rate as f32;
         if self.phase >= 2.0 * PI {
@@ -1,7 +1,26 @@
 
 use nih_plug::prelude::*;
-use reverb::Reverb;
-use std::sync::Arc;
+use nih_plug_egui::EguiState;
+use ambisonics::Encoder;
+use binaural::Binaural;
+use bitcrusher::{Dither, Quantizer};
+use convolution::ConvolutionReverb;
+use delay_buffer::DelayBuffer;
+use ducker::Ducker;
+use gate::{Gate, Release};
+use hybrid::HybridReverb;
+use limiter::Limiter;
+use loader::Loader;
+use oversampler::Filter;
+use reverb::{Placement, Reverb};
+use safety::Guard;
+use saturator::Curve;
+use surround::Surround;
+use tail_detector::TailDetector;
+use velvet::{Envelope, VelvetReverb};
+use vintage::VintageReverb;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
 
 mod all_pass_filter;
 mod comb_filter;
@@ -9,14 +28,338 @@ mod delay_buffer;
 mod reverb;
 mod lfo;
 mod bitcrusher;
+mod biquad;
+mod one_pole;
+mod random;
+mod velvet;
+mod fft;
+mod convolver;
+mod convolution;
+mod wav;
+mod hybrid;
+mod pitch_shifter;
+mod frequency_shifter;
+mod gate;
+mod tail_detector;
+mod ducker;
+mod surround;
+mod ambisonics;
+mod hrtf;
+mod binaural;
+mod vintage;
+mod oversampler;
+mod saturator;
+mod compressor;
+mod limiter;
+mod safety;
+mod loader;
+mod editor;
 
 struct Lashverb {
     params: Arc<LashverbParams>,
     reverb: Reverb,
+    velvet: VelvetReverb,
+    nonlinear: VelvetReverb,
+    convolution: ConvolutionReverb,
+    hybrid: HybridReverb,
+    vintage: VintageReverb,
+    gate: Gate,
+    tail_detector: TailDetector,
+    ducker: Ducker,
+    surround: Surround,
+    ambisonics: Encoder,
+    binaural: Binaural,
+    limiter: Limiter,
+    // Mutes the output after an engine blew up and was reset
+    guard: Guard,
+    // Keeps the auxiliary outputs in line with the main output when there is latency
+    aux_delays: [(DelayBuffer, DelayBuffer); 3],
+    // Keeps the ducking of the auxiliary outputs in line with the limiter
+    duck_gain_delay: DelayBuffer,
+    // The latency last reported to the host
+    latency: u32,
+    // The part of the latency that comes from oversampling the comb tail, the
+    // dry signal is delayed by it to stay in line with the wet signal
+    tail_latency: u32,
+    dry_delay: (DelayBuffer, DelayBuffer),
+    // Set when rendering offline, which has its own oversampling quality
+    offline: bool,
+    // Set for the layouts with a mono main input
+    mono_input: bool,
+    // Set for the stereo to mono layout, where the aux input is the right input
+    // instead of the sidechain
+    right_input: bool,
+    // Used to detect the transport starting and the clear button being pressed
+    was_playing: bool,
+    clear_held: bool,
+    // Builds the engines that need a file in the background
+    loader: Arc<Loader>,
+}
+
+/// Work that is done off the audio thread
+enum Task {
+    /// Reads the impulse response at `ir_path` for the convolution and the
+    /// hybrid engine
+    LoadIr,
+    /// Reads the HRTFs at `hrtf_path` for the binaural output
+    LoadHrtf,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum Engine {
+    #[name = "Freeverb"]
+    Freeverb,
+    #[name = "Velvet"]
+    Velvet,
+    #[name = "Convolution"]
+    Convolution,
+    #[name = "Hybrid"]
+    Hybrid,
+    #[name = "Nonlinear"]
+    Nonlinear,
+    #[name = "Vintage"]
+    Vintage,
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum NonlinearShape {
+    #[name = "Reverse"]
+    Reverse,
+    #[name = "Gated"]
+    Gated,
+    #[name = "Custom"]
+    Custom,
+}
+
+impl NonlinearShape {
+    fn envelope(self) -> Envelope {
+        match self {
+            NonlinearShape::Reverse => Envelope::Reverse,
+            NonlinearShape::Gated => Envelope::Gated,
+            NonlinearShape::Custom => Envelope::Custom,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum GateShape {
+    #[name = "Hard"]
+    Hard,
+    #[name = "Linear"]
+    Linear,
+    #[name = "Exponential"]
+    Exponential,
+}
+
+impl GateShape {
+    fn release(self) -> Release {
+        match self {
+            GateShape::Hard => Release::Hard,
+            GateShape::Linear => Release::Linear,
+            GateShape::Exponential => Release::Exponential,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum CrushPosition {
+    #[name = "Pre"]
+    Pre,
+    #[name = "In Loop"]
+    Loop,
+    #[name = "Post"]
+    Post,
+}
+
+impl CrushPosition {
+    fn placement(self) -> Placement {
+        match self {
+            CrushPosition::Pre => Placement::Pre,
+            CrushPosition::Loop => Placement::Loop,
+            CrushPosition::Post => Placement::Post,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum DriveCurve {
+    #[name = "Tanh"]
+    Tanh,
+    #[name = "Soft Clip"]
+    SoftClip,
+    #[name = "Tube"]
+    Tube,
+    #[name = "Tape"]
+    Tape,
+}
+
+impl DriveCurve {
+    fn curve(self) -> Curve {
+        match self {
+            DriveCurve::Tanh => Curve::Tanh,
+            DriveCurve::SoftClip => Curve::SoftClip,
+            DriveCurve::Tube => Curve::Tube,
+            DriveCurve::Tape => Curve::Tape,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum QuantizerMode {
+    #[name = "Linear"]
+    Linear,
+    #[name = "Mu-Law"]
+    MuLaw,
+    #[name = "A-Law"]
+    ALaw,
+    #[name = "Logarithmic"]
+    Log,
+    #[name = "Vintage 12-Bit"]
+    Vintage,
+}
+
+impl QuantizerMode {
+    fn quantizer(self) -> Quantizer {
+        match self {
+            QuantizerMode::Linear => Quantizer::Linear,
+            QuantizerMode::MuLaw => Quantizer::MuLaw,
+            QuantizerMode::ALaw => Quantizer::ALaw,
+            QuantizerMode::Log => Quantizer::Log,
+            QuantizerMode::Vintage => Quantizer::Vintage,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum DitherType {
+    #[name = "None"]
+    Off,
+    #[name = "TPDF"]
+    Tpdf,
+    #[name = "Noise Shaped"]
+    NoiseShaped,
+}
+
+impl DitherType {
+    fn dither(self) -> Dither {
+        match self {
+            DitherType::Off => Dither::None,
+            DitherType::Tpdf => Dither::Tpdf,
+            DitherType::NoiseShaped => Dither::NoiseShaped,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum GateSync {
+    #[name = "Off"]
+    Off,
+    #[name = "1/32"]
+    ThirtySecond,
+    #[name = "1/16"]
+    Sixteenth,
+    #[name = "1/8"]
+    Eighth,
+    #[name = "1/4"]
+    Quarter,
+    #[name = "1/2"]
+    Half,
+    #[name = "1/1"]
+    Whole,
+}
+
+impl GateSync {
+    /// The hold time in beats, or `None` when the hold time is set in ms
+    fn beats(self) -> Option<f32> {
+        match self {
+            GateSync::Off => None,
+            GateSync::ThirtySecond => Some(0.125),
+            GateSync::Sixteenth => Some(0.25),
+            GateSync::Eighth => Some(0.5),
+            GateSync::Quarter => Some(1.0),
+            GateSync::Half => Some(2.0),
+            GateSync::Whole => Some(4.0),
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum DuckSource {
+    #[name = "Input"]
+    Input,
+    #[name = "Sidechain"]
+    Sidechain,
+}
+
+/// Where the reverb takes its input from. The main input is always passed
+/// through as the dry signal.
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum Routing {
+    #[name = "Main"]
+    Main,
+    #[name = "Sidechain"]
+    Sidechain,
+    #[name = "Main+Sidechain"]
+    MainAndSidechain,
+}
+
+/// The oversampling of the bit crusher.
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum Quality {
+    #[name = "Off"]
+    Off,
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+    #[name = "8x"]
+    X8,
+}
+
+impl Quality {
+    fn factor(self) -> usize {
+        match self {
+            Quality::Off => 1,
+            Quality::X2 => 2,
+            Quality::X4 => 4,
+            Quality::X8 => 8,
+        }
+    }
+}
+
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum OversamplingFilter {
+    #[name = "Minimum Phase"]
+    MinimumPhase,
+    #[name = "Linear Phase"]
+    LinearPhase,
+}
+
+impl OversamplingFilter {
+    fn filter(self) -> Filter {
+        match self {
+            OversamplingFilter::MinimumPhase => Filter::MinimumPhase,
+            OversamplingFilter::LinearPhase => Filter::LinearPhase,
+        }
+    }
+}
+
+/// What the four channel output layout carries. The other layouts have only
+/// one way of being used.
+#[derive(Enum, Debug, PartialEq, Clone, Copy)]
+enum OutputFormat {
+    #[name = "Quad"]
+    Quad,
+    #[name = "AmbiX"]
+    AmbiX,
 }
 
 #[derive(Params)]
 struct LashverbParams {
+    #[id = "engine"]
+    pub engine: EnumParam<Engine>,
+    #[id = "routing"]
+    pub routing: EnumParam<Routing>,
     #[id = "decay"]
     pub decay: FloatParam,
     #[id = "size"]
@@ -27,10 +370,145 @@ struct LashverbParams {
     pub width: FloatParam,
     #[id = "bit_rate"]
     pub bit_rate: FloatParam,
+    #[id = "quantizer"]
+    pub quantizer: EnumParam<QuantizerMode>,
+    #[id = "crush_position"]
+    pub crush_position: EnumParam<CrushPosition>,
+    #[id = "crush_mix"]
+    pub crush_mix: FloatParam,
+    #[id = "downsample"]
+    pub downsample: FloatParam,
+    #[id = "anti_alias"]
+    pub anti_alias: BoolParam,
+    #[id = "quality"]
+    pub quality: EnumParam<Quality>,
+    #[id = "offline_quality"]
+    pub offline_quality: EnumParam<Quality>,
+    #[id = "oversampling_filter"]
+    pub oversampling_filter: EnumParam<OversamplingFilter>,
+    #[id = "crush_noise"]
+    pub crush_noise: FloatParam,
+    #[id = "dither"]
+    pub dither: EnumParam<DitherType>,
     #[id = "wet"]
     pub wet: FloatParam,
     #[id = "dry"]
     pub dry: FloatParam,
+    #[id = "seed"]
+    pub seed: IntParam,
+    #[id = "crossover"]
+    pub crossover: FloatParam,
+    #[id = "vintage_rate"]
+    pub vintage_rate: FloatParam,
+    #[id = "nonlinear_shape"]
+    pub nonlinear_shape: EnumParam<NonlinearShape>,
+    #[id = "nonlinear_length"]
+    pub nonlinear_length: FloatParam,
+    #[id = "custom_point_1_position"]
+    pub custom_point_1_position: FloatParam,
+    #[id = "custom_point_1_level"]
+    pub custom_point_1_level: FloatParam,
+    #[id = "custom_point_2_position"]
+    pub custom_point_2_position: FloatParam,
+    #[id = "custom_point_2_level"]
+    pub custom_point_2_level: FloatParam,
+    #[id = "custom_point_3_position"]
+    pub custom_point_3_position: FloatParam,
+    #[id = "custom_point_3_level"]
+    pub custom_point_3_level: FloatParam,
+    #[id = "custom_point_4_position"]
+    pub custom_point_4_position: FloatParam,
+    #[id = "custom_point_4_level"]
+    pub custom_point_4_level: FloatParam,
+    #[id = "shimmer_amount"]
+    pub shimmer_amount: FloatParam,
+    #[id = "shimmer_pitch"]
+    pub shimmer_pitch: FloatParam,
+    #[id = "shimmer_tone"]
+    pub shimmer_tone: FloatParam,
+    #[id = "freq_shift_bypass"]
+    pub freq_shift_bypass: BoolParam,
+    #[id = "freq_shift_left"]
+    pub freq_shift_left: FloatParam,
+    #[id = "freq_shift_right"]
+    pub freq_shift_right: FloatParam,
+    #[id = "drive"]
+    pub drive: FloatParam,
+    #[id = "drive_curve"]
+    pub drive_curve: EnumParam<DriveCurve>,
+    #[id = "gate"]
+    pub gate: BoolParam,
+    #[id = "gate_threshold"]
+    pub gate_threshold: FloatParam,
+    #[id = "gate_hold"]
+    pub gate_hold: FloatParam,
+    #[id = "gate_sync"]
+    pub gate_sync: EnumParam<GateSync>,
+    #[id = "gate_release"]
+    pub gate_release: FloatParam,
+    #[id = "gate_shape"]
+    pub gate_shape: EnumParam<GateShape>,
+    #[id = "ducking"]
+    pub ducking: BoolParam,
+    #[id = "duck_source"]
+    pub duck_source: EnumParam<DuckSource>,
+    #[id = "duck_threshold"]
+    pub duck_threshold: FloatParam,
+    #[id = "duck_amount"]
+    pub duck_amount: FloatParam,
+    #[id = "duck_attack"]
+    pub duck_attack: FloatParam,
+    #[id = "duck_release"]
+    pub duck_release: FloatParam,
+    #[id = "duck_lookahead"]
+    pub duck_lookahead: FloatParam,
+    #[id = "compressor"]
+    pub compressor: BoolParam,
+    #[id = "comp_threshold"]
+    pub comp_threshold: FloatParam,
+    #[id = "comp_ratio"]
+    pub comp_ratio: FloatParam,
+    #[id = "comp_attack"]
+    pub comp_attack: FloatParam,
+    #[id = "comp_release"]
+    pub comp_release: FloatParam,
+    #[id = "comp_knee"]
+    pub comp_knee: FloatParam,
+    #[id = "limiter"]
+    pub limiter: BoolParam,
+    #[id = "limiter_ceiling"]
+    pub limiter_ceiling: FloatParam,
+    #[id = "surround_balance"]
+    pub surround_balance: FloatParam,
+    #[id = "surround_center"]
+    pub surround_center: FloatParam,
+    #[id = "surround_lfe"]
+    pub surround_lfe: FloatParam,
+    #[id = "output_format"]
+    pub output_format: EnumParam<OutputFormat>,
+    #[id = "diffuseness"]
+    pub diffuseness: FloatParam,
+    #[id = "orientation"]
+    pub orientation: FloatParam,
+    #[id = "binaural"]
+    pub binaural: BoolParam,
+    #[id = "clear_on_start"]
+    pub clear_on_start: BoolParam,
+    #[id = "clear"]
+    pub clear: BoolParam,
+    #[persist = "editor-state"]
+    pub editor_state: Arc<EguiState>,
+    // Path to the impulse response used by the convolution engine, set from
+    // the editor
+    #[persist = "ir_path"]
+    pub ir_path: RwLock<String>,
+    // Path to the head related impulse responses used by the binaural output
+    #[persist = "hrtf_path"]
+    pub hrtf_path: RwLock<String>,
+    // Gain reduction of the compressor and the limiter in dB for the meters,
+    // stored as the bits of an f32
+    pub compressor_reduction: AtomicU32,
+    pub limiter_reduction: AtomicU32,
 }
 
 impl Default for Lashverb {
@@ -38,6 +516,33 @@ impl Default for Lashverb {
         Self {
             params: Arc::new(LashverbParams::default()),
             reverb: Reverb::new(44100),
+            velvet: VelvetReverb::new(44100),
+            nonlinear: VelvetReverb::new(44100),
+            convolution: ConvolutionReverb::new(44100),
+            hybrid: HybridReverb::new(44100),
+            vintage: VintageReverb::new(44100),
+            gate: Gate::new(44100),
+            tail_detector: TailDetector::new(44100),
+            ducker: Ducker::new(44100),
+            surround: Surround::new(44100, 2),
+            ambisonics: Encoder::new(44100),
+            binaural: Binaural::new(),
+            limiter: Limiter::new(44100, 2),
+            guard: Guard::new(44100),
+            aux_delays: aux_delays(44100),
+            duck_gain_delay: DelayBuffer::new(limiter::latency(44100) as usize + 1),
+            latency: 0,
+            tail_latency: 0,
+            dry_delay: (
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+            ),
+            offline: false,
+            mono_input: false,
+            right_input: false,
+            was_playing: false,
+            clear_held: false,
+            loader: Arc::new(Loader::new()),
         }
     }
 }
@@ -45,6 +550,14 @@ impl Default for Lashverb {
 impl Default for LashverbParams {
     fn default() -> Self {
         Self {
+            // --------------------------------------------------------------------------------
+            // Engine
+            engine: EnumParam::new("Engine", Engine::Freeverb),
+
+            // --------------------------------------------------------------------------------
+            // Routing
+            routing: EnumParam::new("Routing", Routing::Main),
+
             // --------------------------------------------------------------------------------
             // Decay
             decay: FloatParam::new(
@@ -119,6 +632,79 @@ impl Default for LashverbParams {
             .with_value_to_string(formatters::v2s_f32_rounded(1))
             ,
 
+            // --------------------------------------------------------------------------------
+            // Quantizer
+            quantizer: EnumParam::new("Quantizer", QuantizerMode::Linear),
+
+            // --------------------------------------------------------------------------------
+            // Crush Position
+            crush_position: EnumParam::new("Crush Position", CrushPosition::Post),
+
+            crush_mix: FloatParam::new(
+                "Crush Mix",
+                1.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Downsample
+            downsample: FloatParam::new(
+                "Downsample",
+                1.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 64.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit("x")
+            .with_value_to_string(formatters::v2s_f32_rounded(2))
+            ,
+
+            // Lowpass before the downsampling, without it the crusher aliases
+            anti_alias: BoolParam::new("Anti-Alias", false),
+
+            // --------------------------------------------------------------------------------
+            // Quality
+            quality: EnumParam::new("Quality", Quality::Off),
+
+            // --------------------------------------------------------------------------------
+            // Offline Quality
+            offline_quality: EnumParam::new("Offline Quality", Quality::X4),
+
+            // --------------------------------------------------------------------------------
+            // Oversampling Filter
+            oversampling_filter: EnumParam::new("Oversampling Filter", OversamplingFilter::MinimumPhase),
+
+            // --------------------------------------------------------------------------------
+            // Noise
+            crush_noise: FloatParam::new(
+                "Noise",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 0.25,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(1))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Dither
+            dither: EnumParam::new("Dither", DitherType::Off),
+
             // --------------------------------------------------------------------------------
             // Wet 
             wet: FloatParam::new(
@@ -152,6 +738,464 @@ impl Default for LashverbParams {
             .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
             .with_string_to_value(formatters::s2v_f32_gain_to_db())
             ,
+
+            // --------------------------------------------------------------------------------
+            // Seed
+            seed: IntParam::new(
+                "Seed",
+                1,
+                IntRange::Linear { min: 1, max: 9999 }
+            )
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Crossover Time
+            crossover: FloatParam::new(
+                "Crossover Time",
+                80.0,
+                FloatRange::Linear {
+                    min: 10.0,
+                    max: hybrid::MAX_CROSSOVER,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Vintage Rate
+            vintage_rate: FloatParam::new(
+                "Vintage Rate",
+                vintage::BUILD_RATE,
+                FloatRange::Linear {
+                    min: vintage::MIN_RATE,
+                    max: vintage::BUILD_RATE,
+                },
+            )
+            .with_value_to_string(formatters::v2s_f32_hz_then_khz(1))
+            .with_string_to_value(formatters::s2v_f32_hz_then_khz())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Nonlinear Shape
+            nonlinear_shape: EnumParam::new("Nonlinear Shape", NonlinearShape::Reverse),
+
+            // --------------------------------------------------------------------------------
+            // Nonlinear Length
+            nonlinear_length: FloatParam::new(
+                "Nonlinear Length",
+                500.0,
+                FloatRange::Skewed {
+                    min: 50.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Custom Envelope
+            // A swell that peaks halfway and then fades out
+            custom_point_1_position: envelope_param("Custom Point 1 Position", 0.0),
+            custom_point_1_level: envelope_param("Custom Point 1 Level", 0.0),
+            custom_point_2_position: envelope_param("Custom Point 2 Position", 0.5),
+            custom_point_2_level: envelope_param("Custom Point 2 Level", 1.0),
+            custom_point_3_position: envelope_param("Custom Point 3 Position", 0.75),
+            custom_point_3_level: envelope_param("Custom Point 3 Level", 0.5),
+            custom_point_4_position: envelope_param("Custom Point 4 Position", 1.0),
+            custom_point_4_level: envelope_param("Custom Point 4 Level", 0.0),
+
+            // --------------------------------------------------------------------------------
+            // Shimmer Amount
+            shimmer_amount: FloatParam::new(
+                "Shimmer Amount",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Shimmer Pitch
+            shimmer_pitch: FloatParam::new(
+                "Shimmer Pitch",
+                12.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 24.0,
+                },
+            )
+            .with_step_size(0.1)
+            .with_unit(" st")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Shimmer Tone
+            shimmer_tone: FloatParam::new(
+                "Shimmer Tone",
+                0.3,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Frequency Shift
+            freq_shift_bypass: BoolParam::new("Freq Shift Bypass", true),
+
+            freq_shift_left: FloatParam::new(
+                "Freq Shift Left",
+                5.0,
+                FloatRange::SymmetricalSkewed {
+                    min: -500.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                    center: 0.0,
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            freq_shift_right: FloatParam::new(
+                "Freq Shift Right",
+                -5.0,
+                FloatRange::SymmetricalSkewed {
+                    min: -500.0,
+                    max: 500.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                    center: 0.0,
+                },
+            )
+            .with_unit(" Hz")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Drive
+            // Saturation in the comb feedback, 0 dB leaves the loop clean
+            drive: FloatParam::new(
+                "Drive",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Drive Curve
+            drive_curve: EnumParam::new("Drive Curve", DriveCurve::Tanh),
+
+            // --------------------------------------------------------------------------------
+            // Gate
+            gate: BoolParam::new("Gate", false),
+
+            gate_threshold: FloatParam::new(
+                "Gate Threshold",
+                -24.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            gate_hold: FloatParam::new(
+                "Gate Hold",
+                250.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            gate_sync: EnumParam::new("Gate Hold Sync", GateSync::Off),
+
+            gate_release: FloatParam::new(
+                "Gate Release",
+                50.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 1000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            gate_shape: EnumParam::new("Gate Shape", GateShape::Hard),
+
+            // --------------------------------------------------------------------------------
+            // Ducking
+            ducking: BoolParam::new("Ducking", false),
+
+            duck_source: EnumParam::new("Duck Source", DuckSource::Input),
+
+            duck_threshold: FloatParam::new(
+                "Duck Threshold",
+                -30.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            duck_amount: FloatParam::new(
+                "Duck Amount",
+                12.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 48.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            duck_attack: FloatParam::new(
+                "Duck Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            duck_release: FloatParam::new(
+                "Duck Release",
+                250.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            duck_lookahead: FloatParam::new(
+                "Duck Lookahead",
+                0.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: ducker::MAX_LOOKAHEAD,
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Compressor
+            // Glues the wet signal together, after the wet gains
+            compressor: BoolParam::new("Compressor", false),
+
+            comp_threshold: FloatParam::new(
+                "Comp Threshold",
+                -18.0,
+                FloatRange::Linear {
+                    min: -60.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            comp_ratio: FloatParam::new(
+                "Comp Ratio",
+                4.0,
+                FloatRange::Skewed {
+                    min: 1.0,
+                    max: 20.0,
+                    factor: FloatRange::skew_factor(-1.5),
+                },
+            )
+            .with_unit(":1")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            comp_attack: FloatParam::new(
+                "Comp Attack",
+                10.0,
+                FloatRange::Skewed {
+                    min: 0.1,
+                    max: 200.0,
+                    factor: FloatRange::skew_factor(-2.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            comp_release: FloatParam::new(
+                "Comp Release",
+                150.0,
+                FloatRange::Skewed {
+                    min: 10.0,
+                    max: 2000.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_unit(" ms")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            comp_knee: FloatParam::new(
+                "Comp Knee",
+                6.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 24.0,
+                },
+            )
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Limiter
+            // Keeps the true peaks of the final output under the ceiling
+            limiter: BoolParam::new("Limiter", false),
+
+            limiter_ceiling: FloatParam::new(
+                "Limiter Ceiling",
+                -1.0,
+                FloatRange::Linear {
+                    min: -24.0,
+                    max: 0.0,
+                },
+            )
+            .with_unit(" dBTP")
+            .with_value_to_string(formatters::v2s_f32_rounded(1))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Surround
+            surround_balance: FloatParam::new(
+                "Front/Rear Balance",
+                0.0,
+                FloatRange::Linear {
+                    min: -1.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage())
+            ,
+
+            surround_center: FloatParam::new(
+                "Center Level",
+                util::db_to_gain(-6.0),
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db())
+            ,
+
+            surround_lfe: FloatParam::new(
+                "LFE Send",
+                0.0,
+                FloatRange::Skewed {
+                    min: 0.0,
+                    max: 1.0,
+                    factor: FloatRange::skew_factor(-1.0),
+                },
+            )
+            .with_smoother(SmoothingStyle::Logarithmic(50.0))
+            .with_unit(" dB")
+            .with_value_to_string(formatters::v2s_f32_gain_to_db(2))
+            .with_string_to_value(formatters::s2v_f32_gain_to_db())
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Ambisonics
+            output_format: EnumParam::new("Output Format", OutputFormat::Quad),
+
+            diffuseness: FloatParam::new(
+                "Diffuseness",
+                1.0,
+                FloatRange::Linear {
+                    min: 0.0,
+                    max: 1.0,
+                },
+            )
+            .with_smoother(SmoothingStyle::Linear(50.0))
+            .with_unit("%")
+            .with_value_to_string(formatters::v2s_f32_percentage(0))
+            .with_string_to_value(formatters::s2v_f32_percentage())
+            ,
+
+            orientation: FloatParam::new(
+                "Orientation",
+                0.0,
+                FloatRange::Linear {
+                    min: -180.0,
+                    max: 180.0,
+                },
+            )
+            .with_unit("°")
+            .with_value_to_string(formatters::v2s_f32_rounded(0))
+            ,
+
+            // --------------------------------------------------------------------------------
+            // Binaural, only used with a stereo output and once HRTFs are loaded
+            binaural: BoolParam::new("Binaural", false),
+
+            // --------------------------------------------------------------------------------
+            // Clear
+            clear_on_start: BoolParam::new("Clear Tail On Start", false),
+
+            // Momentary, the tail is cleared when this turns on
+            clear: BoolParam::new("Clear", false),
+
+            editor_state: editor::default_state(),
+            ir_path: RwLock::new(String::new()),
+            hrtf_path: RwLock::new(String::new()),
+            compressor_reduction: AtomicU32::new(0.0_f32.to_bits()),
+            limiter_reduction: AtomicU32::new(0.0_f32.to_bits()),
             
         }
     }
@@ -165,15 +1209,92 @@ impl Plugin for Lashverb {
 
     const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
-    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[AudioIOLayout {
-        main_input_channels: NonZeroU32::new(2),
-        main_output_channels: NonZeroU32::new(2),
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+
+            aux_input_ports: &[new_nonzero_u32(2)],
+            // The late tail, the early reflections and the bit crushed signal on
+            // their own, the main output still has the full mix. The late tail is
+            // only clean with the crusher on the output, on the input or in the
+            // loop it is crushed like the rest of the tail.
+            aux_output_ports: &[new_nonzero_u32(2), new_nonzero_u32(2), new_nonzero_u32(2)],
+
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                aux_outputs: &["Late", "Early", "Crushed"],
+                ..PortNames::const_default()
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
 
-        aux_input_ports: &[],
-        aux_output_ports: &[],
+            // Stereo sidechain for the ducker or as the reverb input
+            aux_input_ports: &[new_nonzero_u32(2)],
+            aux_output_ports: &[],
 
-        names: PortNames::const_default(),
-    }];
+            names: PortNames {
+                aux_inputs: &["Sidechain"],
+                ..PortNames::const_default()
+            },
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+
+            aux_input_ports: &[],
+            aux_output_ports: &[],
+
+            names: PortNames::const_default(),
+        },
+        // Surround returns for a stereo send, each extra speaker gets its own
+        // decorrelated tail. The speakers are in L R C LFE Ls Rs Lss Rss order.
+        // The four channel layout can also be first order Ambisonics.
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(4),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(6),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(8),
+            ..AudioIOLayout::const_default()
+        },
+        // Mono input is fed to both sets of combs, which decorrelates it
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(2),
+            ..AudioIOLayout::const_default()
+        },
+        // Stereo to mono. The main buffer only has as many channels as there are
+        // outputs, so the right input comes in on its own port.
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+
+            aux_input_ports: &[new_nonzero_u32(1)],
+            aux_output_ports: &[],
+
+            names: PortNames {
+                layout: Some("Stereo to Mono"),
+                main_input: Some("Left"),
+                aux_inputs: &["Right"],
+                ..PortNames::const_default()
+            },
+        },
+    ];
 
 
     const MIDI_INPUT: MidiConfig = MidiConfig::None;
@@ -182,31 +1303,88 @@ impl Plugin for Lashverb {
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        editor::create(self.params.clone(), self.loader.clone(), async_executor)
+    }
+
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let loader = self.loader.clone();
+        Box::new(move |task| match task {
+            Task::LoadIr => {
+                let ir_path = params.ir_path.read().map(|p| p.clone()).unwrap_or_default();
+                if let Err(err) = loader.load_ir(&ir_path) {
+                    nih_log!("Could not load impulse response '{}': {}", ir_path, err);
+                }
+            }
+            Task::LoadHrtf => {
+                let hrtf_path = params.hrtf_path.read().map(|p| p.clone()).unwrap_or_default();
+                if let Err(err) = loader.load_hrtf(&hrtf_path) {
+                    nih_log!("Could not load HRTFs '{}': {}", hrtf_path, err);
+                }
+            }
+        })
+    }
+
     fn initialize(
         &mut self,
-        _audio_io_layout: &AudioIOLayout,
+        audio_io_layout: &AudioIOLayout,
         _buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         let sample_rate = _buffer_config.sample_rate as usize;
+        self.offline = _buffer_config.process_mode == ProcessMode::Offline;
+        self.mono_input = audio_io_layout.main_input_channels == NonZeroU32::new(1);
+        self.right_input = audio_io_layout.names.aux_inputs == ["Right"];
         self.reverb = Reverb::new(sample_rate);
+        self.velvet = VelvetReverb::new(sample_rate);
+        self.nonlinear = VelvetReverb::new(sample_rate);
+        self.convolution = ConvolutionReverb::new(sample_rate);
+        self.hybrid = HybridReverb::new(sample_rate);
+        self.vintage = VintageReverb::new(sample_rate);
+        self.gate = Gate::new(sample_rate);
+        self.tail_detector = TailDetector::new(sample_rate);
+        self.ducker = Ducker::new(sample_rate);
+        self.ambisonics = Encoder::new(sample_rate);
+        self.binaural = Binaural::new();
+        let output_channels = audio_io_layout.main_output_channels.map_or(2, |c| c.get() as usize);
+        self.surround = Surround::new(sample_rate, output_channels);
+        self.limiter = Limiter::new(sample_rate, output_channels);
+        self.guard = Guard::new(sample_rate);
+        self.aux_delays = aux_delays(sample_rate);
+        self.duck_gain_delay = DelayBuffer::new(limiter::latency(sample_rate) as usize + 1);
+        self.dry_delay.0.reset();
+        self.dry_delay.1.reset();
+
+        // The files are loaded right here, there is no audio running yet
+        self.loader.set_sample_rate(sample_rate);
+        context.execute(Task::LoadIr);
+        context.execute(Task::LoadHrtf);
+        self.loader.convolution.swap_into(&mut self.convolution);
+        self.loader.hybrid.swap_into(&mut self.hybrid);
+        self.loader.binaural.swap_into(&mut self.binaural);
+
+        self.tail_latency = self.oversampling_latency();
+        self.latency = self.ducker_latency() + self.limiter_latency() + self.tail_latency;
+        context.set_latency_samples(self.latency);
         true
     }
 
     fn reset(&mut self) {
+        self.clear_tail();
     }
 
     fn process(
         &mut self,
         buffer: &mut Buffer,
-        _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
         /*
         for channel_samples in buffer.iter_samples() {
@@ -219,29 +1397,491 @@ impl Plugin for Lashverb {
 
         let num_samples = buffer.samples();
         let out = buffer.as_slice();
+        let (sidechain, right_input) = match aux.inputs.first() {
+            Some(port) if self.right_input => (None, Some(port.as_slice_immutable())),
+            port => (port.map(|sc| sc.as_slice_immutable()), None),
+        };
+        let mut aux_outputs = aux.outputs.iter_mut();
+        let mut late_output = aux_outputs.next().map(|port| port.as_slice());
+        let mut early_output = aux_outputs.next().map(|port| port.as_slice());
+        let mut crushed_output = aux_outputs.next().map(|port| port.as_slice());
+
+        let playing = context.transport().playing;
+        let transport_started = playing && !self.was_playing;
+        self.was_playing = playing;
+
+        // Engines built from a file the editor loaded are swapped in between blocks
+        self.loader.convolution.swap_into(&mut self.convolution);
+        self.loader.hybrid.swap_into(&mut self.hybrid);
+        self.loader.binaural.swap_into(&mut self.binaural);
+
+        let clear = self.params.clear.value();
+        if (transport_started && self.params.clear_on_start.value()) || (clear && !self.clear_held) {
+            self.clear_tail();
+        }
+        self.clear_held = clear;
+
+        // The breakpoints can be set in any order
+        let mut points: [(f32, f32); velvet::MAX_POINTS] = [
+            (self.params.custom_point_1_position.value(), self.params.custom_point_1_level.value()),
+            (self.params.custom_point_2_position.value(), self.params.custom_point_2_level.value()),
+            (self.params.custom_point_3_position.value(), self.params.custom_point_3_level.value()),
+            (self.params.custom_point_4_position.value(), self.params.custom_point_4_level.value()),
+        ];
+        points.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        self.nonlinear.points(&points);
+
+        // The lookahead delays the whole output, so the host has to know about it.
+        // The oversampling only changes between blocks for the same reason.
+        self.tail_latency = self.oversampling_latency();
+        let latency = self.ducker_latency() + self.limiter_latency() + self.tail_latency;
+        if latency != self.latency {
+            self.latency = latency;
+            context.set_latency_samples(latency);
+        }
+
+        // A tempo synced hold time overrides the one in ms
+        let gate_hold = match (self.params.gate_sync.value().beats(), context.transport().tempo) {
+            (Some(beats), Some(tempo)) => beats * 60000.0 / tempo as f32,
+            _ => self.params.gate_hold.value(),
+        };
+
+        // With a mono output the reverb runs without crossfeed and only the left
+        // tail is used. Summing both sides would comb filter wherever they are
+        // still correlated.
+        let mono_output = out.len() < 2;
+        let width = if mono_output { 1.0 } else { self.params.width.value() };
+        let ambisonics = out.len() == 4 && self.params.output_format.value() == OutputFormat::AmbiX;
+        let surround = out.len() > 2 && !ambisonics;
+        let binaural = out.len() == 2 && self.params.binaural.value() && self.binaural.is_loaded();
+        let limit = self.params.limiter.value();
+        let limiter_latency = self.limiter_latency() as usize;
+        let channels = out.len().min(limiter::MAX_CHANNELS);
+        let mut limiter_reduction: f32 = 0.0;
+        if limit {
+            self.limiter.set_ceiling(self.params.limiter_ceiling.value());
+        }
 
         for i in 0..num_samples {
-            self.reverb.decay(self.params.decay.value());
-            self.reverb.size(self.params.size.value() as usize);
-            self.reverb.damp(self.params.damp.value());
-            self.reverb.width(self.params.width.value());
-            self.reverb.wet(self.params.wet.value());
-            self.reverb.dry(self.params.dry.value());
-            // For the bit crusher
-            self.reverb.set_idx(i);
-            self.reverb.set_size(num_samples);
-            self.reverb.set_bit_rate(self.params.bit_rate.value());
-
-            let (in_left, in_right) = (out[0][i], out[1][i]);
-            let (out_left, out_right) = self.reverb.process((in_left, in_right));
-            out[0][i] = out_left;
-            out[1][i] = out_right;
+            let input = if let Some([right, ..]) = right_input {
+                // Both sides go to both sets of combs, only the left tail is used
+                let mid = (out[0][i] + right[i]) * 0.5;
+                (mid, mid)
+            } else if self.mono_input {
+                (out[0][i], out[0][i])
+            } else {
+                (out[0][i], out[1][i])
+            };
+            let sc_input = stereo_sample(sidechain, i);
+            let reverb_input = match self.params.routing.value() {
+                Routing::Main => input,
+                Routing::Sidechain => sc_input,
+                Routing::MainAndSidechain => (input.0 + sc_input.0, input.1 + sc_input.1),
+            };
+
+            if surround {
+                self.surround.balance(self.params.surround_balance.value());
+                self.surround.center(self.params.surround_center.value());
+                self.surround.lfe(self.params.surround_lfe.value());
+            }
+            if ambisonics {
+                self.ambisonics.diffuseness(self.params.diffuseness.value());
+                self.ambisonics.orientation(self.params.orientation.value());
+            }
+
+            let (mut wet_left, mut wet_right) = match self.params.engine.value() {
+                Engine::Freeverb => {
+                    self.reverb.decay(self.params.decay.value());
+                    self.reverb.damp(self.params.damp.value());
+                    self.reverb.width(width);
+                    self.reverb.wet(self.params.wet.value());
+                    set_tail_params(&mut self.reverb, &self.params);
+
+                    self.reverb.process(reverb_input)
+                }
+                Engine::Velvet => {
+                    self.velvet.decay(self.params.decay.value());
+                    // Size is used as the tap density
+                    self.velvet.size(self.params.size.value() as usize);
+                    self.velvet.damp(self.params.damp.value());
+                    self.velvet.width(width);
+                    self.velvet.wet(self.params.wet.value());
+                    self.velvet.seed(self.params.seed.value() as u32);
+
+                    self.velvet.process(reverb_input)
+                }
+                Engine::Convolution => {
+                    self.convolution.shape(self.params.decay.value(), self.params.damp.value());
+                    self.convolution.width(width);
+                    self.convolution.wet(self.params.wet.value());
+
+                    self.convolution.process(reverb_input)
+                }
+                Engine::Hybrid => {
+                    set_tail_params(self.hybrid.tail(), &self.params);
+                    // Decay and damp are matched to the impulse response when one is loaded
+                    self.hybrid.decay(self.params.decay.value());
+                    self.hybrid.damp(self.params.damp.value());
+                    self.hybrid.crossover(self.params.crossover.value());
+                    self.hybrid.width(width);
+                    self.hybrid.wet(self.params.wet.value());
+
+                    self.hybrid.process(reverb_input)
+                }
+                Engine::Nonlinear => {
+                    self.nonlinear.envelope(self.params.nonlinear_shape.value().envelope());
+                    self.nonlinear.length(self.params.nonlinear_length.value());
+                    // Size is used as the tap density
+                    self.nonlinear.size(self.params.size.value() as usize);
+                    self.nonlinear.damp(self.params.damp.value());
+                    self.nonlinear.width(width);
+                    self.nonlinear.wet(self.params.wet.value());
+                    self.nonlinear.seed(self.params.seed.value() as u32);
+
+                    self.nonlinear.process(reverb_input)
+                }
+                Engine::Vintage => {
+                    let reverb = self.vintage.reverb();
+                    reverb.decay(self.params.decay.value());
+                    reverb.damp(self.params.damp.value());
+                    reverb.width(width);
+                    reverb.wet(self.params.wet.value());
+                    set_tail_params(reverb, &self.params);
+                    self.vintage.rate(self.params.vintage_rate.value());
+
+                    self.vintage.process(reverb_input)
+                }
+            };
+
+            // Nothing after the engine gets to see a NaN or a runaway level, the
+            // engine is reset after the block
+            (wet_left, wet_right) = self.guard.check((wet_left, wet_right));
+
+            // Split the wet signal up for the auxiliary outputs. Only the hybrid
+            // engine has separate early reflections, and only the comb tail is crushed.
+            // With the crusher before the output the late part is crushed as well.
+            let wet = (wet_left, wet_right);
+            let mut parts = match self.params.engine.value() {
+                Engine::Freeverb => [self.reverb.uncrushed(), (0.0, 0.0), wet],
+                Engine::Hybrid => {
+                    let early = self.hybrid.early_out();
+                    [
+                        self.hybrid.tail().uncrushed(),
+                        early,
+                        (wet.0 - early.0, wet.1 - early.1),
+                    ]
+                }
+                _ => [wet, (0.0, 0.0), (0.0, 0.0)],
+            };
+            if self.guard.failed() {
+                parts = [(0.0, 0.0); 3];
+            }
+
+            // Rendered before the gate and the ducker, they only change the level
+            if binaural {
+                let early = match self.params.engine.value() {
+                    Engine::Hybrid => self.hybrid.early_out(),
+                    _ => (0.0, 0.0),
+                };
+                (wet_left, wet_right) =
+                    self.binaural.process(early, (wet_left - early.0, wet_right - early.1));
+            }
+
+            // Only the wet signal is gated, and only in gate mode
+            if self.params.gate.value() {
+                self.gate.set_threshold(util::db_to_gain(self.params.gate_threshold.value()));
+                self.gate.set_hold(gate_hold);
+                self.gate.set_release_time(self.params.gate_release.value());
+                self.gate.set_release(self.params.gate_shape.value().release());
+
+                let gain = self.gate.process(reverb_input.0.abs().max(reverb_input.1.abs()));
+                wet_left *= gain;
+                wet_right *= gain;
+                for part in parts.iter_mut() {
+                    *part = (part.0 * gain, part.1 * gain);
+                }
+            }
+
+            // The tail only ends once both the main and the reverb input are silent
+            self.tail_detector.process(
+                (input.0.abs().max(reverb_input.0.abs()), input.1.abs().max(reverb_input.1.abs())),
+                (wet_left, wet_right),
+            );
+
+            let dry = self.params.dry.value();
+            let mut dry = (input.0 * dry, input.1 * dry);
+            if self.tail_latency > 0 {
+                let latency = self.tail_latency as usize;
+                dry = (
+                    self.dry_delay.0.delay(dry.0, latency),
+                    self.dry_delay.1.delay(dry.1, latency),
+                );
+            }
+
+            if self.params.ducking.value() {
+                self.ducker.set_threshold(self.params.duck_threshold.value());
+                self.ducker.set_amount(self.params.duck_amount.value());
+                self.ducker.set_attack(self.params.duck_attack.value());
+                self.ducker.set_release(self.params.duck_release.value());
+                self.ducker.set_lookahead(self.params.duck_lookahead.value());
+
+                // Without a connected sidechain there is nothing to duck from
+                let key = match self.params.duck_source.value() {
+                    DuckSource::Input => input.0.abs().max(input.1.abs()),
+                    DuckSource::Sidechain => sc_input.0.abs().max(sc_input.1.abs()),
+                };
+
+                ((wet_left, wet_right), dry) =
+                    self.ducker.process(key, (wet_left, wet_right), dry);
+            }
+
+            // The auxiliary outputs are gated and ducked like the main output. The
+            // parts are late by the tail latency already, the ducking gain is applied
+            // to them once they are as late as the ducked wet signal.
+            let lookahead = (self.latency - self.tail_latency) as usize;
+            if lookahead > 0 {
+                for (part, delay) in parts.iter_mut().zip(self.aux_delays.iter_mut()) {
+                    *part = (
+                        delay.0.delay(part.0, lookahead),
+                        delay.1.delay(part.1, lookahead),
+                    );
+                }
+            }
+            let duck_gain = if self.params.ducking.value() { self.ducker.gain() } else { 1.0 };
+            let duck_gain = if limiter_latency > 0 {
+                self.duck_gain_delay.delay(duck_gain, limiter_latency)
+            } else {
+                duck_gain
+            };
+            // Fades the output back in after a reset
+            let gain = self.guard.next_gain();
+            for part in parts.iter_mut() {
+                *part = (part.0 * duck_gain * gain, part.1 * duck_gain * gain);
+            }
+            write_stereo_sample(&mut late_output, i, parts[0]);
+            write_stereo_sample(&mut early_output, i, parts[1]);
+            write_stereo_sample(&mut crushed_output, i, parts[2]);
+
+            if mono_output {
+                out[0][i] = wet_left + dry.0;
+            } else if ambisonics {
+                let frame = self.ambisonics.process((wet_left, wet_right), dry);
+                for (channel, sample) in out.iter_mut().zip(frame) {
+                    channel[i] = sample;
+                }
+            } else if surround {
+                // The dry signal only goes to the front pair
+                let frame = self.surround.process((wet_left, wet_right));
+                for (channel, sample) in out.iter_mut().zip(frame) {
+                    channel[i] = *sample;
+                }
+                out[0][i] += dry.0;
+                out[1][i] += dry.1;
+            } else {
+                out[0][i] = wet_left + dry.0;
+                out[1][i] = wet_right + dry.1;
+            }
+
+            if gain < 1.0 {
+                for channel in out.iter_mut() {
+                    channel[i] *= gain;
+                }
+            }
+
+            // The limiter is the very last thing on every output channel
+            if limit {
+                let mut frame = [0.0; limiter::MAX_CHANNELS];
+                for (sample, channel) in frame.iter_mut().zip(out.iter()) {
+                    *sample = channel[i];
+                }
+                self.limiter.process(&mut frame[..channels]);
+                for (channel, sample) in out.iter_mut().zip(frame) {
+                    channel[i] = sample;
+                }
+                limiter_reduction = limiter_reduction.max(self.limiter.reduction());
+            }
+        }
+
+        // A comb can run away or take in a NaN some time before it shows in the
+        // output, so every delay line is checked once per block as well
+        let stable = match self.params.engine.value() {
+            Engine::Freeverb => self.reverb.is_stable(),
+            Engine::Hybrid => self.hybrid.tail().is_stable(),
+            Engine::Vintage => self.vintage.reverb().is_stable(),
+            _ => true,
+        };
+        if let Some(fault) = self.guard.end_block(stable) {
+            self.clear_tail();
+            // Only logged once until the output is back, logging may allocate
+            if fault.report {
+                util::permit_alloc(|| {
+                    nih_log!("The reverb blew up from {}, it was reset", fault.cause)
+                });
+            }
         }
 
-        ProcessStatus::Normal
+        let compressor_reduction = match self.params.engine.value() {
+            Engine::Freeverb => self.reverb.compressor().reduction(),
+            Engine::Hybrid => self.hybrid.tail().compressor().reduction(),
+            Engine::Vintage => self.vintage.reverb().compressor().reduction(),
+            _ => 0.0,
+        };
+        self.params.compressor_reduction.store(compressor_reduction.to_bits(), Ordering::Relaxed);
+        self.params.limiter_reduction.store(limiter_reduction.to_bits(), Ordering::Relaxed);
+
+        // Let the host know how much tail is left so silent instances can be
+        // put to sleep without cutting anything off
+        let tail_time = match self.params.engine.value() {
+            Engine::Freeverb => self.reverb.decay_time(),
+            Engine::Velvet => self.velvet.tail_time(),
+            Engine::Convolution => self.convolution.tail_time(),
+            Engine::Hybrid => self.hybrid.tail_time(),
+            Engine::Nonlinear => self.nonlinear.tail_time(),
+            Engine::Vintage => self.vintage.tail_time(),
+        };
+
+        match self.tail_detector.remaining(tail_time) {
+            Some(samples) => ProcessStatus::Tail(samples),
+            None => ProcessStatus::KeepAlive,
+        }
     }
 }
 
+impl Lashverb {
+    /// Flushes the state of every engine so no stale tail plays back
+    fn clear_tail(&mut self) {
+        self.reverb.reset();
+        self.velvet.reset();
+        self.nonlinear.reset();
+        self.convolution.reset();
+        self.hybrid.reset();
+        self.vintage.reset();
+        self.gate.reset();
+        self.tail_detector.reset();
+        self.ducker.reset();
+        self.surround.reset();
+        self.ambisonics.reset();
+        self.binaural.reset();
+        self.limiter.reset();
+        self.dry_delay.0.reset();
+        self.dry_delay.1.reset();
+        for delay in self.aux_delays.iter_mut() {
+            delay.0.reset();
+            delay.1.reset();
+        }
+        self.duck_gain_delay.reset();
+    }
+
+    /// Sets the oversampling of the comb tail of the current engine, returns the
+    /// latency it adds in samples
+    fn oversampling_latency(&mut self) -> u32 {
+        let quality = if self.offline {
+            self.params.offline_quality.value()
+        } else {
+            self.params.quality.value()
+        };
+        let filter = self.params.oversampling_filter.value().filter();
+        let placement = self.params.crush_position.value().placement();
+
+        // The vintage engine runs at its own rate and isn't oversampled
+        let reverb = match self.params.engine.value() {
+            Engine::Freeverb => &mut self.reverb,
+            Engine::Hybrid => self.hybrid.tail(),
+            _ => return 0,
+        };
+        reverb.set_oversampling(quality.factor(), filter);
+        reverb.set_crush_placement(placement);
+        reverb.latency() as u32
+    }
+
+    /// The latency of the limiter in samples when it is on
+    fn limiter_latency(&self) -> u32 {
+        if self.params.limiter.value() {
+            self.limiter.latency()
+        } else {
+            0
+        }
+    }
+
+    /// The latency in samples, which is the ducker's lookahead when it is on
+    fn ducker_latency(&mut self) -> u32 {
+        if self.params.ducking.value() {
+            self.ducker.set_lookahead(self.params.duck_lookahead.value());
+            self.ducker.latency()
+        } else {
+            0
+        }
+    }
+}
+
+/// Returns the stereo sample at `idx` of an auxiliary input, a mono input is
+/// used for both channels and a missing one is silent.
+fn stereo_sample(buffer: Option<&[&mut [f32]]>, idx: usize) -> (f32, f32) {
+    match buffer {
+        Some([left, right, ..]) => (left[idx], right[idx]),
+        Some([mono]) => (mono[idx], mono[idx]),
+        _ => (0.0, 0.0),
+    }
+}
+
+/// Writes `value` to the stereo auxiliary output at `idx`, if it is connected.
+fn write_stereo_sample(buffer: &mut Option<&mut [&mut [f32]]>, idx: usize, value: (f32, f32)) {
+    match buffer.as_deref_mut() {
+        Some([left, right, ..]) => {
+            left[idx] = value.0;
+            right[idx] = value.1;
+        }
+        Some([mono]) => mono[idx] = (value.0 + value.1) * 0.5,
+        _ => (),
+    }
+}
+
+/// Delay lines for the three stereo auxiliary outputs, long enough for the
+/// longest lookahead and the limiter.
+fn aux_delays(sample_rate: usize) -> [(DelayBuffer, DelayBuffer); 3] {
+    let len = (ducker::MAX_LOOKAHEAD * 0.001 * sample_rate as f32) as usize
+        + limiter::latency(sample_rate) as usize
+        + 1;
+    std::array::from_fn(|_| (DelayBuffer::new(len), DelayBuffer::new(len)))
+}
+
+/// A position or a level of a breakpoint of the custom nonlinear envelope,
+/// both go from 0% to 100%.
+fn envelope_param(name: &str, default: f32) -> FloatParam {
+    FloatParam::new(name, default, FloatRange::Linear { min: 0.0, max: 1.0 })
+        .with_unit("%")
+        .with_value_to_string(formatters::v2s_f32_percentage(0))
+        .with_string_to_value(formatters::s2v_f32_percentage())
+}
+
+/// Sets the parameters that are shared by every engine that uses the comb tail.
+fn set_tail_params(reverb: &mut Reverb, params: &LashverbParams) {
+    reverb.size(params.size.value() as usize);
+    reverb.set_bit_rate(params.bit_rate.value());
+    reverb.set_downsample(params.downsample.value());
+    reverb.set_anti_alias(params.anti_alias.value());
+    reverb.set_noise_level(params.crush_noise.value());
+    reverb.set_dither(params.dither.value().dither());
+    reverb.set_quantizer(params.quantizer.value().quantizer());
+    reverb.set_crush_placement(params.crush_position.value().placement());
+    reverb.set_crush_mix(params.crush_mix.value());
+    reverb.shimmer_amount(params.shimmer_amount.value());
+    reverb.shimmer_pitch(params.shimmer_pitch.value());
+    reverb.shimmer_tone(params.shimmer_tone.value());
+    reverb.freq_shift(params.freq_shift_left.value(), params.freq_shift_right.value());
+    reverb.freq_shift_bypass(params.freq_shift_bypass.value());
+    reverb.set_drive(params.drive.value());
+    reverb.set_drive_curve(params.drive_curve.value().curve());
+    reverb.set_compress(params.compressor.value());
+
+    let compressor = reverb.compressor();
+    compressor.set_threshold(params.comp_threshold.value());
+    compressor.set_ratio(params.comp_ratio.value());
+    compressor.set_attack(params.comp_attack.value());
+    compressor.set_release(params.comp_release.value());
+    compressor.set_knee(params.comp_knee.value());
+}
+
 impl ClapPlugin for Lashverb {
     const CLAP_ID: &'static str = "com.lashlight.lashverb";
     const CLAP_DESCRIPTION: Option<&'static str> = Some("A simple Freeverb-based reverb that aims at not sounding right");
@@ -251,6 +1891,8 @@ impl ClapPlugin for Lashverb {
     const CLAP_FEATURES: &'static [ClapFeature] = &[
         ClapFeature::AudioEffect, 
         ClapFeature::Stereo, 
+        ClapFeature::Mono, 
+        ClapFeature::Surround, 
         ClapFeature::Reverb
     ];
 }
@@ -0,0 +1,162 @@
+// A lookahead limiter for the final output that keeps true peaks under the
+// ceiling. The peaks between the samples are estimated by interpolating three
+// points between every two samples, like a 4x oversampled true peak meter.
+//
+// The gain each peak needs is held for the length of the lookahead and then
+// averaged over it, so the gain has ramped all the way down by the time the
+// peak comes out of the delay and never overshoots. All channels get the
+// same gain.
+
+use std::f32::consts::PI;
+
+/// The most channels the limiter can handle
+pub const MAX_CHANNELS: usize = 8;
+/// The lookahead in ms
+pub const LOOKAHEAD: f32 = 1.5;
+
+// Samples on each side of the interpolated points, the interpolation lags this
+// many samples behind
+const TAPS: usize = 4;
+// Release time in ms
+const RELEASE: f32 = 100.0;
+
+/// The length of the lookahead window in samples
+fn window(sample_rate: usize) -> usize {
+    ((LOOKAHEAD * 0.001 * sample_rate as f32) as usize).max(1)
+}
+
+/// Returns the latency of the limiter at `sample_rate` in samples
+pub fn latency(sample_rate: usize) -> u32 {
+    (window(sample_rate) - 1 + TAPS) as u32
+}
+
+pub struct Limiter {
+    channels: usize,
+    ceiling: f32,
+    release_coef: f32,
+    // Filter taps for the points at a quarter, half and three quarters of the
+    // way between two samples
+    taps: [[f32; 2 * TAPS]; 3],
+    // The last input samples of every channel for the interpolation, newest
+    // last, and the delay lines for the lookahead
+    history: [[f32; 2 * TAPS]; MAX_CHANNELS],
+    delays: Vec<Vec<f32>>,
+    delay_pos: usize,
+    // The gain each of the last peaks needs and the gain after the hold and
+    // the release, for the lookahead window
+    targets: Vec<f32>,
+    held: Vec<f32>,
+    pos: usize,
+    release: f32,
+    // The gain reduction of the last sample in dB
+    reduction: f32,
+}
+
+impl Limiter {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    /// * `channels` - the number of channels, up to `MAX_CHANNELS`
+    pub fn new(sample_rate: usize, channels: usize) -> Self {
+        let window = window(sample_rate);
+        let channels = channels.min(MAX_CHANNELS);
+
+        // Windowed sinc interpolation, scaled so a constant signal stays the same
+        let taps = std::array::from_fn(|point| {
+            let fraction = (point + 1) as f32 * 0.25;
+            let taps: [f32; 2 * TAPS] = std::array::from_fn(|i| {
+                let t = i as f32 - (TAPS - 1) as f32 - fraction;
+                let sinc = (PI * t).sin() / (PI * t);
+                let window = 0.5 + 0.5 * (PI * t / TAPS as f32).cos();
+                sinc * window
+            });
+            let sum: f32 = taps.iter().sum();
+            taps.map(|tap| tap / sum)
+        });
+
+        Self {
+            channels,
+            ceiling: 1.0,
+            release_coef: (-1.0 / (RELEASE * 0.001 * sample_rate as f32)).exp(),
+            taps,
+            history: [[0.0; 2 * TAPS]; MAX_CHANNELS],
+            delays: vec![vec![0.0; window + TAPS]; channels],
+            delay_pos: 0,
+            targets: vec![1.0; window],
+            held: vec![1.0; window],
+            pos: 0,
+            release: 1.0,
+            reduction: 0.0,
+        }
+    }
+
+    /// Sets the ceiling in dB
+    pub fn set_ceiling(&mut self, ceiling: f32) {
+        self.ceiling = 10.0_f32.powf(ceiling / 20.0);
+    }
+
+    /// Returns the latency in samples
+    pub fn latency(&self) -> u32 {
+        (self.targets.len() - 1 + TAPS) as u32
+    }
+
+    /// Returns the gain reduction of the last sample in dB
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+
+    pub fn reset(&mut self) {
+        self.history = [[0.0; 2 * TAPS]; MAX_CHANNELS];
+        for delay in self.delays.iter_mut() {
+            delay.fill(0.0);
+        }
+        self.delay_pos = 0;
+        self.targets.fill(1.0);
+        self.held.fill(1.0);
+        self.pos = 0;
+        self.release = 1.0;
+        self.reduction = 0.0;
+    }
+
+    /// Limits one sample of every channel in `frame` in place. The output is
+    /// delayed by `latency()`.
+    pub fn process(&mut self, frame: &mut [f32]) {
+        let window = self.targets.len();
+
+        let mut peak: f32 = 0.0;
+        for (x, history) in frame.iter().zip(self.history.iter_mut()).take(self.channels) {
+            history.rotate_left(1);
+            history[2 * TAPS - 1] = *x;
+
+            peak = peak.max(history[TAPS - 1].abs());
+            for taps in self.taps.iter() {
+                let y: f32 = taps.iter().zip(history.iter()).map(|(tap, x)| tap * x).sum();
+                peak = peak.max(y.abs());
+            }
+        }
+
+        self.targets[self.pos] = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+
+        // Hold the lowest gain of the window, and release from there
+        let lowest = self.targets.iter().fold(1.0_f32, |a, &b| a.min(b));
+        self.release = if lowest < self.release {
+            lowest
+        } else {
+            lowest + (self.release - lowest) * self.release_coef
+        };
+        self.held[self.pos] = self.release;
+        let gain = self.held.iter().sum::<f32>() / window as f32;
+        self.reduction = -20.0 * gain.log10();
+
+        // A sample comes out of the delay when the gain for it has ramped all
+        // the way down, one window after its interpolation is done
+        let delay_len = window + TAPS;
+        let read = (self.delay_pos + 1) % delay_len;
+        for (x, delay) in frame.iter_mut().zip(self.delays.iter_mut()) {
+            delay[self.delay_pos] = *x;
+            *x = delay[read] * gain;
+        }
+        self.delay_pos = read;
+        self.pos = (self.pos + 1) % window;
+    }
+}
@@ -0,0 +1,171 @@
+// Files are read and the engines that use them are built on a background
+// thread. The audio thread only swaps a finished engine in, without blocking,
+// and leaves the one it replaced behind so that is freed off the audio thread
+// as well, the next time something is loaded.
+
+use crate::binaural::Binaural;
+use crate::convolution::ConvolutionReverb;
+use crate::hrtf::Hrtf;
+use crate::hybrid::HybridReverb;
+use crate::wav::Wav;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, RwLock};
+
+enum State<T> {
+    Empty,
+    Loaded(T),
+    Replaced(T),
+}
+
+/// Hands a value built in the background over to the audio thread.
+pub struct Slot<T> {
+    state: Mutex<State<T>>,
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slot<T> {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State::Empty),
+        }
+    }
+
+    /// Stores a newly built `value`, whatever was in the slot is dropped here.
+    /// Must not be called from the audio thread.
+    pub fn store(&self, value: T) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = State::Loaded(value);
+        }
+    }
+
+    /// Swaps a newly built value with `current` when there is one. Never
+    /// blocks, allocates or frees, so it can be called from the audio thread.
+    pub fn swap_into(&self, current: &mut T) -> bool {
+        let Ok(mut state) = self.state.try_lock() else {
+            return false;
+        };
+        match std::mem::replace(&mut *state, State::Empty) {
+            State::Loaded(mut value) => {
+                std::mem::swap(current, &mut value);
+                *state = State::Replaced(value);
+                true
+            }
+            other => {
+                *state = other;
+                false
+            }
+        }
+    }
+}
+
+/// Builds the engines that need a file.
+pub struct Loader {
+    sample_rate: AtomicU32,
+    pub convolution: Slot<ConvolutionReverb>,
+    pub hybrid: Slot<HybridReverb>,
+    pub binaural: Slot<Binaural>,
+    // What happened to the last impulse response and HRTFs, for the editor
+    ir_status: RwLock<String>,
+    hrtf_status: RwLock<String>,
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: AtomicU32::new(44100),
+            convolution: Slot::new(),
+            hybrid: Slot::new(),
+            binaural: Slot::new(),
+            ir_status: RwLock::new(String::new()),
+            hrtf_status: RwLock::new(String::new()),
+        }
+    }
+
+    /// Sets the sample rate the engines are built for
+    pub fn set_sample_rate(&self, sample_rate: usize) {
+        self.sample_rate.store(sample_rate as u32, Ordering::Relaxed);
+    }
+
+    /// Builds the convolution and the hybrid engine for the impulse response
+    /// at `path`, an empty path unloads it.
+    pub fn load_ir(&self, path: &str) -> Result<(), &'static str> {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed) as usize;
+        let mut convolution = ConvolutionReverb::new(sample_rate);
+        let mut hybrid = HybridReverb::new(sample_rate);
+
+        let loaded = if path.is_empty() {
+            Ok(())
+        } else {
+            Wav::read(path).and_then(|wav| {
+                let wav = wav.resampled(sample_rate as f32);
+                convolution.load(&wav)?;
+                hybrid.load(&wav)
+            })
+        };
+
+        set_status(&self.ir_status, match (&loaded, path.is_empty()) {
+            (Err(err), _) => err,
+            (Ok(()), true) => "No impulse response loaded",
+            (Ok(()), false) => "Loaded",
+        });
+        if loaded.is_ok() {
+            self.convolution.store(convolution);
+            self.hybrid.store(hybrid);
+        }
+        loaded
+    }
+
+    /// Sets up the binaural output for the HRTFs at `path`, an empty path
+    /// unloads them.
+    pub fn load_hrtf(&self, path: &str) -> Result<(), &'static str> {
+        let sample_rate = self.sample_rate.load(Ordering::Relaxed) as f32;
+        let mut binaural = Binaural::new();
+
+        let loaded = if path.is_empty() {
+            Ok(())
+        } else {
+            Hrtf::read(path).map(|hrtf| binaural.load(&hrtf.resampled(sample_rate)))
+        };
+
+        set_status(&self.hrtf_status, match (&loaded, path.is_empty()) {
+            (Err(err), _) => err,
+            (Ok(()), true) => "No HRTFs loaded",
+            (Ok(()), false) => "Loaded",
+        });
+        if loaded.is_ok() {
+            self.binaural.store(binaural);
+        }
+        loaded
+    }
+
+    /// Returns what happened to the last impulse response
+    pub fn ir_status(&self) -> String {
+        get_status(&self.ir_status)
+    }
+
+    /// Returns what happened to the last HRTFs
+    pub fn hrtf_status(&self) -> String {
+        get_status(&self.hrtf_status)
+    }
+}
+
+fn get_status(status: &RwLock<String>) -> String {
+    status.read().map(|s| s.clone()).unwrap_or_default()
+}
+
+fn set_status(status: &RwLock<String>, value: &str) {
+    if let Ok(mut s) = status.write() {
+        *s = value.to_string();
+    }
+}
@@ -0,0 +1,39 @@
+// A one pole lowpass filter, the same kind of filter the combs use for damping
+
+pub struct OnePole {
+    state: f32,
+    damp: f32,
+    damp_inv: f32,
+}
+
+impl Default for OnePole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OnePole {
+    pub fn new() -> Self {
+        Self {
+            state: 0.0,
+            damp: 0.0,
+            damp_inv: 1.0,
+        }
+    }
+
+    /// Sets the amount of damping, 0.0 lets everything through and values
+    /// closer to 1.0 cut more of the high end.
+    pub fn set_damp(&mut self, value: f32) {
+        self.damp = value;
+        self.damp_inv = 1.0 - value;
+    }
+
+    pub fn reset(&mut self) {
+        self.state = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.state = input * self.damp_inv + self.state * self.damp;
+        self.state
+    }
+}
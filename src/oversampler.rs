@@ -0,0 +1,435 @@
+// Polyphase oversampling for the nonlinear stages. The signal is upsampled in
+// stages of 2x up to 8x, processed at the high rate and brought back down, so
+// whatever the nonlinearity adds above the Nyquist frequency is filtered out
+// instead of folding back.
+//
+// Every stage is a pair of half-band filters, either polyphase IIR allpass
+// pairs with a low and frequency dependent delay, or linear phase FIR filters
+// with a fixed delay. The later stages only see signal that is band-limited
+// already, so they get away with shorter filters.
+
+use std::f64::consts::PI;
+
+/// The highest oversampling factor
+pub const MAX_FACTOR: usize = 8;
+
+// Number of allpass coefficients and the transition band, relative to the
+// lower rate, of the IIR filter of every stage
+const IIR_DESIGNS: [(usize, f64); 3] = [(8, 0.04), (4, 0.2), (3, 0.3)];
+// Half the length of the FIR filter of every stage, in samples at the higher
+// rate. These have to be even to keep the delays whole samples.
+const FIR_HALF_LENGTHS: [usize; 3] = [64, 16, 8];
+// Kaiser window shape of the FIR filters, about 80 dB of stopband attenuation
+const KAISER_BETA: f64 = 8.0;
+
+/// The latency in samples at the highest factor with linear phase filters
+pub const MAX_LATENCY: usize = FIR_HALF_LENGTHS[0] + FIR_HALF_LENGTHS[1] / 2 + FIR_HALF_LENGTHS[2] / 4;
+
+/// The kind of half-band filters used.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Polyphase IIR filters, little latency but the phase is bent near the top
+    MinimumPhase,
+    /// FIR filters, no phase distortion but more latency
+    LinearPhase,
+}
+
+/// A delay line that keeps every sample twice, so the newest `len` samples
+/// can always be read as one slice.
+struct History {
+    data: Vec<f32>,
+    pos: usize,
+}
+
+impl History {
+    fn new(len: usize) -> Self {
+        Self {
+            data: vec![0.0; 2 * len],
+            pos: 0,
+        }
+    }
+
+    fn push(&mut self, x: f32) {
+        let len = self.data.len() / 2;
+        self.pos = (self.pos + len - 1) % len;
+        self.data[self.pos] = x;
+        self.data[self.pos + len] = x;
+    }
+
+    /// The newest samples, newest first
+    fn samples(&self) -> &[f32] {
+        &self.data[self.pos..self.pos + self.data.len() / 2]
+    }
+
+    fn reset(&mut self) {
+        self.data.fill(0.0);
+    }
+}
+
+/// A polyphase half-band filter made of two chains of first order allpasses,
+/// as described by Valenzuela and Constantinides.
+struct IirHalfband {
+    // The even coefficients are in the first chain, the odd ones in the second
+    coefs: Vec<f32>,
+    // The last input and output of every allpass
+    states: Vec<(f32, f32)>,
+}
+
+impl IirHalfband {
+    fn new(coefs: &[f32]) -> Self {
+        Self {
+            coefs: coefs.to_vec(),
+            states: vec![(0.0, 0.0); coefs.len()],
+        }
+    }
+
+    /// Runs `x` through the allpass chain `path`, 0 or 1
+    fn path(&mut self, path: usize, x: f32) -> f32 {
+        let mut x = x;
+        for (coef, state) in self.coefs.iter().zip(self.states.iter_mut()).skip(path).step_by(2) {
+            let y = (x - state.1) * coef + state.0;
+            *state = (x, y);
+            x = y;
+        }
+        x
+    }
+
+    fn upsample(&mut self, x: f32) -> [f32; 2] {
+        [self.path(0, x), self.path(1, x)]
+    }
+
+    fn downsample(&mut self, x: [f32; 2]) -> f32 {
+        (self.path(0, x[1]) + self.path(1, x[0])) * 0.5
+    }
+
+    /// The delay of upsampling and downsampling with this filter at low
+    /// frequencies, in samples at the lower rate
+    fn latency(&self) -> f64 {
+        // Each chain has half of the allpasses and one of them is a sample
+        // late, at the higher rate
+        let delay: f64 = self.coefs.iter().map(|&a| (1.0 - a as f64) / (1.0 + a as f64)).sum();
+        delay + 0.5
+    }
+
+    fn reset(&mut self) {
+        self.states.fill((0.0, 0.0));
+    }
+}
+
+/// A linear phase half-band FIR filter. Every other tap is zero, so only the
+/// odd taps are kept, and the center tap is always one half.
+struct FirHalfband {
+    // The taps at the odd positions from the start to the end of the filter
+    taps: Vec<f32>,
+    // The samples that meet the odd taps, and the ones that meet the center tap
+    history: History,
+    center: History,
+}
+
+impl FirHalfband {
+    fn new(taps: &[f32]) -> Self {
+        let half_len = taps.len();
+        Self {
+            taps: taps.to_vec(),
+            history: History::new(half_len),
+            center: History::new(half_len / 2 + 1),
+        }
+    }
+
+    fn odd_taps(&self) -> f32 {
+        self.taps.iter().zip(self.history.samples()).map(|(tap, x)| tap * x).sum()
+    }
+
+    fn upsample(&mut self, x: f32) -> [f32; 2] {
+        self.history.push(x);
+        let center = self.history.samples()[self.taps.len() / 2];
+        // Twice the gain, half of the upsampled signal is zeros
+        [center, 2.0 * self.odd_taps()]
+    }
+
+    fn downsample(&mut self, x: [f32; 2]) -> f32 {
+        self.center.push(x[0]);
+        let y = 0.5 * self.center.samples()[self.taps.len() / 2] + self.odd_taps();
+        self.history.push(x[1]);
+        y
+    }
+
+    fn reset(&mut self) {
+        self.history.reset();
+        self.center.reset();
+    }
+}
+
+/// The filters for one 2x step, up and down.
+struct Stage {
+    iir: (IirHalfband, IirHalfband),
+    fir: (FirHalfband, FirHalfband),
+}
+
+impl Stage {
+    fn new(iir_design: (usize, f64), fir_half_len: usize) -> Self {
+        let coefs = iir_coefs(iir_design.0, iir_design.1);
+        let taps = fir_taps(fir_half_len);
+
+        Self {
+            iir: (IirHalfband::new(&coefs), IirHalfband::new(&coefs)),
+            fir: (FirHalfband::new(&taps), FirHalfband::new(&taps)),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.iir.0.reset();
+        self.iir.1.reset();
+        self.fir.0.reset();
+        self.fir.1.reset();
+    }
+}
+
+pub struct Oversampler {
+    stages: [Stage; 3],
+    factor: usize,
+    filter: Filter,
+}
+
+impl Default for Oversampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Oversampler {
+    ///
+    /// Constructor, starts without oversampling.
+    pub fn new() -> Self {
+        Self {
+            stages: std::array::from_fn(|i| Stage::new(IIR_DESIGNS[i], FIR_HALF_LENGTHS[i])),
+            factor: 1,
+            filter: Filter::MinimumPhase,
+        }
+    }
+
+    /// Sets the oversampling factor, 1, 2, 4 or 8
+    pub fn set_factor(&mut self, factor: usize) {
+        let factor = factor.clamp(1, MAX_FACTOR).next_power_of_two();
+        if factor != self.factor {
+            self.factor = factor;
+            self.reset();
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: Filter) {
+        if filter != self.filter {
+            self.filter = filter;
+            self.reset();
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Returns the delay the up and downsampling add, in samples rounded to the
+    /// nearest whole sample
+    pub fn latency(&self) -> usize {
+        let stages = self.stages.iter().take(self.factor.trailing_zeros() as usize);
+        match self.filter {
+            Filter::LinearPhase => {
+                FIR_HALF_LENGTHS.iter().enumerate().take(stages.len()).map(|(i, len)| len >> i).sum()
+            }
+            Filter::MinimumPhase => {
+                // Every stage runs at twice the rate of the one before
+                let latency: f64 = stages
+                    .enumerate()
+                    .map(|(i, stage)| stage.iir.0.latency() / (1 << i) as f64)
+                    .sum();
+                latency.round() as usize
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for stage in self.stages.iter_mut() {
+            stage.reset();
+        }
+    }
+
+    /// Runs `x` through `process` at the oversampled rate, returns the output
+    /// at the original rate.
+    pub fn process(&mut self, x: f32, mut process: impl FnMut(f32) -> f32) -> f32 {
+        if self.factor == 1 {
+            return process(x);
+        }
+
+        let stages = self.factor.trailing_zeros() as usize;
+        let mut buffer = [0.0; MAX_FACTOR];
+        let mut upsampled = [0.0; MAX_FACTOR];
+        buffer[0] = x;
+
+        let mut len = 1;
+        for stage in self.stages[..stages].iter_mut() {
+            for (x, out) in buffer[..len].iter().zip(upsampled.chunks_exact_mut(2)) {
+                let y = match self.filter {
+                    Filter::MinimumPhase => stage.iir.0.upsample(*x),
+                    Filter::LinearPhase => stage.fir.0.upsample(*x),
+                };
+                out.copy_from_slice(&y);
+            }
+            len *= 2;
+            buffer[..len].copy_from_slice(&upsampled[..len]);
+        }
+
+        for x in buffer[..len].iter_mut() {
+            *x = process(*x);
+        }
+
+        for stage in self.stages[..stages].iter_mut().rev() {
+            len /= 2;
+            for i in 0..len {
+                let pair = [buffer[2 * i], buffer[2 * i + 1]];
+                buffer[i] = match self.filter {
+                    Filter::MinimumPhase => stage.iir.1.downsample(pair),
+                    Filter::LinearPhase => stage.fir.1.downsample(pair),
+                };
+            }
+        }
+
+        buffer[0]
+    }
+}
+
+/// The allpass coefficients of an IIR half-band filter with `count`
+/// coefficients and a transition band of `transition`, after Laurent de Soras'
+/// HIIR library.
+fn iir_coefs(count: usize, transition: f64) -> Vec<f32> {
+    let k = ((1.0 - transition * 2.0) * PI / 4.0).tan().powi(2);
+    let kksqrt = (1.0 - k * k).powf(0.25);
+    let e = 0.5 * (1.0 - kksqrt) / (1.0 + kksqrt);
+    let e4 = e.powi(4);
+    let q = e * (1.0 + e4 * (2.0 + e4 * (15.0 + 150.0 * e4)));
+    let order = (count * 2 + 1) as f64;
+
+    (1..=count)
+        .map(|c| {
+            let c = c as f64;
+            let mut num = 0.0;
+            let mut den = 0.0;
+            for i in 0..16 {
+                let i_f = i as f64;
+                let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+                num += sign * q.powf(i_f * (i_f + 1.0)) * ((i_f * 2.0 + 1.0) * c * PI / order).sin();
+                den -= sign * q.powf((i_f + 1.0).powi(2)) * ((i_f + 1.0) * 2.0 * c * PI / order).cos();
+            }
+            let ww = num * q.powf(0.25) / (den + 0.5);
+            let wwsq = ww * ww;
+            let x = ((1.0 - wwsq * k) * (1.0 - wwsq / k)).sqrt() / (1.0 + wwsq);
+            ((1.0 - x) / (1.0 + x)) as f32
+        })
+        .collect()
+}
+
+/// The odd taps of a Kaiser windowed half-band FIR filter that is
+/// `2 * half_len + 1` taps long, scaled so they add up to one half.
+fn fir_taps(half_len: usize) -> Vec<f32> {
+    let bessel = |x: f64| {
+        // The zeroth order modified Bessel function of the first kind
+        let mut sum = 1.0;
+        let mut term = 1.0;
+        for k in 1..32 {
+            term *= (x / (2.0 * k as f64)).powi(2);
+            sum += term;
+        }
+        sum
+    };
+
+    let taps: Vec<f64> = (0..half_len)
+        .map(|i| {
+            let m = (2 * i + 1) as f64 - half_len as f64;
+            let sinc = (PI * m / 2.0).sin() / (PI * m);
+            let window = bessel(KAISER_BETA * (1.0 - (m / half_len as f64).powi(2)).sqrt()) / bessel(KAISER_BETA);
+            sinc * window
+        })
+        .collect();
+
+    let sum: f64 = taps.iter().sum();
+    taps.iter().map(|tap| (tap * 0.5 / sum) as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 2] = [Filter::MinimumPhase, Filter::LinearPhase];
+    const FACTORS: [usize; 3] = [2, 4, 8];
+
+    fn oversampler(filter: Filter, factor: usize) -> Oversampler {
+        let mut oversampler = Oversampler::new();
+        oversampler.set_filter(filter);
+        oversampler.set_factor(factor);
+        oversampler
+    }
+
+    /// The peak output once the filters settled, of `process` running at the
+    /// high rate on silence
+    fn peak(oversampler: &mut Oversampler, mut process: impl FnMut(f32) -> f32) -> f32 {
+        (0..20000)
+            .map(|_| oversampler.process(0.0, &mut process))
+            .skip(2000)
+            .fold(0.0, |peak, y| peak.max(y.abs()))
+    }
+
+    #[test]
+    fn passband_has_unity_gain() {
+        for filter in FILTERS {
+            for factor in FACTORS {
+                let mut oversampler = oversampler(filter, factor);
+                let w = 2.0 * std::f32::consts::PI * 1000.0 / 44100.0;
+                let peak = (0..44100)
+                    .map(|n| oversampler.process((w * n as f32).sin(), |x| x))
+                    .skip(4410)
+                    .fold(0.0_f32, |peak, y| peak.max(y.abs()));
+                assert!((peak - 1.0).abs() < 1e-3, "{}x: {}", factor, peak);
+            }
+        }
+    }
+
+    #[test]
+    fn stopband_is_rejected() {
+        // Tones made at the high rate above the original Nyquist frequency, as a
+        // fraction of the original rate
+        for filter in FILTERS {
+            for factor in FACTORS {
+                let mut oversampler = oversampler(filter, factor);
+                for tone in [0.6, 0.75, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5] {
+                    if tone >= factor as f64 / 2.0 {
+                        continue;
+                    }
+                    oversampler.reset();
+                    let mut n = 0;
+                    let peak = peak(&mut oversampler, |_| {
+                        let phase = (tone / factor as f64 * n as f64).fract();
+                        n += 1;
+                        (2.0 * PI * phase).sin() as f32
+                    });
+                    let db = 20.0 * peak.log10();
+                    assert!(db < -80.0, "{}x, {} of the rate: {} dB", factor, tone, db);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn impulse_peaks_at_the_latency() {
+        for filter in FILTERS {
+            for factor in FACTORS {
+                let mut oversampler = oversampler(filter, factor);
+                let output: Vec<f32> = (0..200)
+                    .map(|n| oversampler.process(if n == 0 { 1.0 } else { 0.0 }, |x| x))
+                    .collect();
+                let peak = (0..output.len())
+                    .max_by(|a, b| output[*a].abs().total_cmp(&output[*b].abs()))
+                    .unwrap();
+                assert_eq!(peak, oversampler.latency(), "{}x", factor);
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// A delay line pitch shifter. Two read taps sweep through a short delay line at
+// a speed set by the pitch ratio, and are crossfaded with complementary windows
+// so that the jump back at the end of each sweep is not heard.
+
+use std::f32::consts::PI;
+
+pub struct PitchShifter {
+    buffer: Vec<f32>,
+    mask: usize,
+    write_pos: usize,
+    // Length of a single sweep in samples
+    window: f32,
+    // Position of the first tap within the sweep, from 0.0 to 1.0
+    phase: f32,
+    ratio: f32,
+}
+
+impl PitchShifter {
+    ///
+    /// Constructor
+    /// * `window` - length of a sweep in samples, around 50 ms works well
+    pub fn new(window: usize) -> Self {
+        let len = (window + 2).next_power_of_two();
+
+        Self {
+            buffer: vec![0.0; len],
+            mask: len - 1,
+            write_pos: 0,
+            window: window as f32,
+            phase: 0.0,
+            ratio: 1.0,
+        }
+    }
+
+    /// Sets the shift in semitones.
+    pub fn set_semitones(&mut self, semitones: f32) {
+        self.ratio = 2.0_f32.powf(semitones / 12.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+        self.phase = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.buffer[self.write_pos] = input;
+
+        // Reading faster than writing shortens the delay and raises the pitch
+        self.phase += (1.0 - self.ratio) / self.window;
+        self.phase -= self.phase.floor();
+        let phase_2 = (self.phase + 0.5).fract();
+
+        // sin² and cos² windows always add up to one
+        let gain = (PI * self.phase).sin().powi(2);
+        let output = self.read(self.phase * self.window) * gain
+            + self.read(phase_2 * self.window) * (1.0 - gain);
+
+        self.write_pos = (self.write_pos + 1) & self.mask;
+        output
+    }
+
+    /// Reads `delay` samples back with linear interpolation.
+    fn read(&self, delay: f32) -> f32 {
+        let whole = delay as usize;
+        let frac = delay - whole as f32;
+        let a = self.buffer[self.write_pos.wrapping_sub(whole) & self.mask];
+        let b = self.buffer[self.write_pos.wrapping_sub(whole + 1) & self.mask];
+        a + (b - a) * frac
+    }
+}
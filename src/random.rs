@@ -0,0 +1,31 @@
+// A tiny xorshift pseudo random number generator. It is fully deterministic
+// for a given seed so things like velvet noise sequences are reproducible.
+
+pub struct Random {
+    state: u32,
+}
+
+impl Random {
+    /// Creates a new generator. A seed of zero would lock xorshift at zero
+    /// forever, so it is replaced with a non-zero constant.
+    pub fn new(seed: u32) -> Self {
+        Self {
+            state: if seed == 0 { 0x9E37_79B9 } else { seed },
+        }
+    }
+
+    /// Returns the next raw 32 bit value.
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// Returns a value between 0.0 (inclusive) and 1.0 (exclusive).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
@@ -1,7 +1,13 @@
 use crate::comb_filter::Comb;
 use crate::all_pass_filter::AllPass;
+use crate::compressor::Compressor;
 use crate::lfo::Lfo;
-use crate::bitcrusher::BitCrusher;
+use crate::bitcrusher::{BitCrusher, Dither, Quantizer};
+use crate::delay_buffer::DelayBuffer;
+use crate::one_pole::OnePole;
+use crate::oversampler::{self, Filter, Oversampler};
+use crate::pitch_shifter::PitchShifter;
+use crate::saturator::Curve;
 
 // Add this to any of the constants below to create stereo difference, for example:
 //     use COMB_TUNING_1 on the left stereo channel
@@ -24,6 +30,26 @@ const AP_TUN_2: usize = 331;
 const AP_TUN_3: usize = 431;
 const AP_TUN_4: usize = 547;
 
+/// Where the bit crusher sits in the reverb.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// On the input, before the combs
+    Pre,
+    /// In the feedback path of every comb, so the tail degrades as it recirculates
+    Loop,
+    /// On the output of the allpasses
+    Post,
+}
+
+// Length of the shimmer pitch shifter window in ms
+const SHIMMER_WINDOW: usize = 50;
+// The most the shimmer loop can amplify anything at full amount. No comb can
+// make a signal louder than 1 / (1 - feedback) times, while the pitch shifter and
+// the tone filter only ever average samples, so the shimmer is scaled with
+// 1 - feedback to keep the whole loop below one at any pitch. A frozen tail
+// loses nothing, so nothing is fed back.
+const SHIMMER_LOOP_GAIN: f32 = 0.9;
+
 pub struct Reverb {
     // 8 comb filters for left and right channels
     combs: [(Comb, Comb); 8], 
@@ -34,28 +60,53 @@ pub struct Reverb {
     wet_gains: (f32, f32),
     wet: f32, 
     width: f32, 
-    dry: f32,
     input_gain: f32, 
     damp: f32, 
     decay: f32, 
     size: usize, 
     lfo: Lfo,
     freeze: bool, 
-    bitcrusher: BitCrusher, 
-    buffer_data: BufferData,
-    counter: f32,
-    fadeout_factor: f32,
-}
-
-pub struct BufferData {
-    buffer_idx: usize,
-    buffer_size: usize
+    // One bit crusher per channel, they hold samples and keep filter states
+    bitcrusher: (BitCrusher, BitCrusher), 
+    // Run the bit crushers on the input or output at a higher rate
+    oversamplers: (Oversampler, Oversampler),
+    // The downsampling factor of the bit crusher at the session rate
+    downsample: f32,
+    crush_placement: Placement,
+    // Amount of the crushed signal mixed in where the crusher sits
+    crush_mix: f32,
+    sample_rate: f32,
+    // Pitch shifters for the shimmer feedback, the tone filter and the last
+    // shifted output that is fed back into the combs
+    shifters: (PitchShifter, PitchShifter),
+    shimmer_tone: (OnePole, OnePole),
+    shimmer_feedback: (f32, f32),
+    shimmer_amount: f32,
+    // The gain of the shimmer feedback at full amount, from the comb feedback
+    shimmer_gain: f32,
+    shimmer_pitch: f32,
+    // Frequency shift of the left and right comb feedback in Hz
+    freq_shift: (f32, f32),
+    freq_shift_bypass: bool,
+    // Compresses the output after the wet gains when it is on
+    compressor: Compressor,
+    compress: bool,
+    // The last output before the bit crusher, with the wet gains applied
+    uncrushed: (f32, f32),
+    // Keeps the uncrushed output in line with the oversampled crusher
+    uncrushed_delay: (DelayBuffer, DelayBuffer),
 }
 
 fn calc_len(length: usize, sample_rate: usize) -> usize {
     length * sample_rate / 44100
 }
 
+/// Crushes `input` and mixes it with the clean signal by `mix`. Both go through
+/// the oversampler, so they stay in phase.
+fn crush(crusher: &mut BitCrusher, oversampler: &mut Oversampler, input: f32, mix: f32) -> f32 {
+    oversampler.process(input, |x| x + (crusher.process(x) - x) * mix)
+}
+
 impl Reverb {
     ///
     /// Constructor
@@ -63,36 +114,36 @@ impl Reverb {
     pub fn new(sample_rate: usize) -> Self {
         let cbs = [
             (
-                Comb::new(calc_len(COMB_TUN_1, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_1 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_1, sample_rate), 1), 
+                Comb::new(calc_len(COMB_TUN_1 + OFFSET, sample_rate), 2)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_2, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_2 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_2, sample_rate), 3), 
+                Comb::new(calc_len(COMB_TUN_2 + OFFSET, sample_rate), 4)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_3, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_3 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_3, sample_rate), 5), 
+                Comb::new(calc_len(COMB_TUN_3 + OFFSET, sample_rate), 6)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_4, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_4 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_4, sample_rate), 7), 
+                Comb::new(calc_len(COMB_TUN_4 + OFFSET, sample_rate), 8)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_5, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_5 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_5, sample_rate), 9), 
+                Comb::new(calc_len(COMB_TUN_5 + OFFSET, sample_rate), 10)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_6, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_6 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_6, sample_rate), 11), 
+                Comb::new(calc_len(COMB_TUN_6 + OFFSET, sample_rate), 12)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_7, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_7 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_7, sample_rate), 13), 
+                Comb::new(calc_len(COMB_TUN_7 + OFFSET, sample_rate), 14)
             ),
             (
-                Comb::new(calc_len(COMB_TUN_8, sample_rate)), 
-                Comb::new(calc_len(COMB_TUN_8 + OFFSET, sample_rate))
+                Comb::new(calc_len(COMB_TUN_8, sample_rate), 15), 
+                Comb::new(calc_len(COMB_TUN_8 + OFFSET, sample_rate), 16)
             )
         ];
 
@@ -121,17 +172,36 @@ impl Reverb {
             wet_gains: (0.0, 0.0), 
             wet: 0.8,
             width: 1.0, 
-            dry: 0.2, 
             input_gain: 1.0, 
             damp: 0.5, 
             decay: 0.67, 
             size: COMB_TUN_1 + OFFSET,
             lfo: Lfo::new(sample_rate), 
             freeze: false, 
-            bitcrusher: BitCrusher::new(), 
-            buffer_data: BufferData::new(),
-            counter: 0.0,
-            fadeout_factor: 0.0,
+            bitcrusher: (BitCrusher::new(17), BitCrusher::new(18)), 
+            oversamplers: (Oversampler::new(), Oversampler::new()),
+            downsample: 1.0,
+            crush_placement: Placement::Post,
+            crush_mix: 1.0,
+            sample_rate: sample_rate as f32,
+            shifters: (
+                PitchShifter::new(SHIMMER_WINDOW * sample_rate / 1000),
+                PitchShifter::new(SHIMMER_WINDOW * sample_rate / 1000),
+            ),
+            shimmer_tone: (OnePole::new(), OnePole::new()),
+            shimmer_feedback: (0.0, 0.0),
+            shimmer_amount: 0.0,
+            shimmer_gain: 0.0,
+            shimmer_pitch: 0.0,
+            freq_shift: (0.0, 0.0),
+            freq_shift_bypass: true,
+            compressor: Compressor::new(sample_rate),
+            compress: false,
+            uncrushed: (0.0, 0.0),
+            uncrushed_delay: (
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+                DelayBuffer::new(oversampler::MAX_LATENCY),
+            ),
         };
 
         // Init the reverb and return
@@ -140,11 +210,51 @@ impl Reverb {
         reverb
     }
 
-    /// Processes the `input`, returns it with reverb applied.
+    /// Clears the state of every filter, without allocating
+    pub fn reset(&mut self) {
+        for comb in self.combs.iter_mut() {
+            comb.0.reset();
+            comb.1.reset();
+        }
+        for allpass in self.allpasses.iter_mut() {
+            allpass.0.reset();
+            allpass.1.reset();
+        }
+        self.lfo.reset();
+        self.bitcrusher.0.reset();
+        self.bitcrusher.1.reset();
+        self.oversamplers.0.reset();
+        self.oversamplers.1.reset();
+        self.uncrushed_delay.0.reset();
+        self.uncrushed_delay.1.reset();
+        self.shifters.0.reset();
+        self.shifters.1.reset();
+        self.shimmer_tone.0.reset();
+        self.shimmer_tone.1.reset();
+        self.shimmer_feedback = (0.0, 0.0);
+        self.compressor.reset();
+    }
+
+    /// Processes the `input`, returns the reverb signal without the dry signal.
     pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let input = if self.crush_placement == Placement::Pre {
+            (
+                crush(&mut self.bitcrusher.0, &mut self.oversamplers.0, input.0, self.crush_mix),
+                crush(&mut self.bitcrusher.1, &mut self.oversamplers.1, input.1, self.crush_mix),
+            )
+        } else {
+            input
+        };
+
         let in_sum = (input.0 + input.1) * 0.015 * self.input_gain;
         let mut out = (0.0, 0.0);
 
+        // The pitch shifted output of the previous sample goes back into the combs
+        let shimmer = (
+            self.shimmer_feedback.0 * self.shimmer_amount * self.shimmer_gain,
+            self.shimmer_feedback.1 * self.shimmer_amount * self.shimmer_gain,
+        );
+
         self.lfo.set_speed(44100.0);
         self.lfo.set_amp(20.0);
 
@@ -159,8 +269,17 @@ impl Reverb {
             c.0.set_delay(mod_delay as usize);
             c.1.set_delay(mod_delay as usize);
 
-            out.0 += c.0.process(input.0 * 0.015 + in_sum);
-            out.1 += c.1.process(input.1 * 0.015 + in_sum);
+            out.0 += c.0.process(input.0 * 0.015 + in_sum + shimmer.0);
+            out.1 += c.1.process(input.1 * 0.015 + in_sum + shimmer.1);
+        }
+
+        // Pitch shift the comb output for the next sample's shimmer. It is taken
+        // before the allpasses, which can amplify some frequencies.
+        if self.shimmer_amount > 0.0 {
+            self.shimmer_feedback = (
+                self.shimmer_tone.0.process(self.shifters.0.process(out.0)),
+                self.shimmer_tone.1.process(self.shifters.1.process(out.1)),
+            );
         }
 
         // Apply allpass filters
@@ -169,51 +288,65 @@ impl Reverb {
             out.1 = a.1.process(out.1, 0.5);
         }
 
-        // Apply bit crush
-        out.0 = self.bitcrusher.process(
-            out.0, 
-            self.buffer_data.buffer_idx, 
-            self.buffer_data.buffer_size
-        );
-        out.1 = self.bitcrusher.process(
-            out.1, 
-            self.buffer_data.buffer_idx, 
-            self.buffer_data.buffer_size
+        self.uncrushed = (
+            out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1,
+            out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1,
         );
 
-        // Increase the counter if the input signal falls below 0.002;
-        // reset the counter if input signal is received
-        self.counter = if (input.0 < 0.0002) && (input.1 < 0.0002) {
-            self.counter + 0.001
-        } else {
-            0.0
-        };
-
-        // Apply fade out gradually, and make sure the fadeout factor
-        // never becomes negative.
-        self.fadeout_factor = (1.0 - self.counter / 500.0).max(0.0);
+        // Apply bit crush
+        if self.crush_placement == Placement::Post {
+            out.0 = crush(&mut self.bitcrusher.0, &mut self.oversamplers.0, out.0, self.crush_mix);
+            out.1 = crush(&mut self.bitcrusher.1, &mut self.oversamplers.1, out.1, self.crush_mix);
+
+            let latency = self.oversamplers.0.latency();
+            if latency > 0 {
+                self.uncrushed = (
+                    self.uncrushed_delay.0.delay(self.uncrushed.0, latency),
+                    self.uncrushed_delay.1.delay(self.uncrushed.1, latency),
+                );
+            }
+        }
 
         // Get reverb and crossfeed
         let (mut rev_0, mut rev_1) = (
-            // main out               + crossfeed out             * fadeout       
-            (out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1) * self.fadeout_factor,
-            (out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1) * self.fadeout_factor,
+            // main out               + crossfeed out
+            out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1,
+            out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1,
         );
 
-        // Denormalize!
-        if !rev_0.is_normal() {
+        if self.compress {
+            (rev_0, rev_1) = self.compressor.process((rev_0, rev_1));
+        }
+
+        // Denormalize! NaNs and infinities are left for the safety to find
+        if rev_0.is_subnormal() {
             rev_0 = 0.0;
         }
 
-        if !rev_1.is_normal() {
+        if rev_1.is_subnormal() {
             rev_1 = 0.0;
         }
 
-        // Return reverb and dry signal
-        (
-            rev_0 + input.0 * self.dry, 
-            rev_1 + input.1 * self.dry,
-        )
+        (rev_0, rev_1)
+    }
+
+    /// Returns false when any comb took in a NaN, an infinity or a runaway
+    /// level since the last call. Call it once per block, `reset()` clears it.
+    pub fn is_stable(&mut self) -> bool {
+        // Check every comb, so all of them start over
+        let mut stable = true;
+        for comb in self.combs.iter_mut() {
+            stable &= comb.0.is_stable();
+            stable &= comb.1.is_stable();
+        }
+        stable
+    }
+
+    /// Returns the last reverb output without the output bit crusher applied.
+    /// With the crusher on the input or in the loop the tail itself is crushed,
+    /// and this is the same as the output.
+    pub fn uncrushed(&self) -> (f32, f32) {
+        self.uncrushed
     }
 
     /// Update
@@ -230,11 +363,7 @@ impl Reverb {
             comb.1.set_feedback(feedback);
             comb.1.set_damp(damp);
         }
-    }
-
-    /// Sets the dry signal level
-    pub fn dry(&mut self, dry: f32) {
-        self.dry = dry;
+        self.shimmer_gain = SHIMMER_LOOP_GAIN * (1.0 - feedback) / self.combs.len() as f32;
     }
 
     /// Sets the wet signal level
@@ -275,33 +404,357 @@ impl Reverb {
         self.update();
     }
 
+    /// Returns the time in seconds it takes the longest comb to decay by 60 dB
+    /// with the current decay and size, ignoring damping.
+    pub fn decay_time(&self) -> f32 {
+        if self.freeze {
+            return f32::INFINITY;
+        }
+
+        -3.0 * self.longest_delay() / (self.sample_rate * self.decay.log10())
+    }
+
+    /// Returns the normalized decay value that makes the longest comb decay by
+    /// 60 dB in `time` seconds with the current size. This is the inverse of
+    /// `decay_time()`, clamped to the range `decay()` accepts.
+    pub fn decay_for_time(&self, time: f32) -> f32 {
+        let feedback = 10.0_f32.powf(-3.0 * self.longest_delay() / (self.sample_rate * time));
+        ((feedback - 0.7) / 0.27).clamp(0.0, 1.0)
+    }
+
+    /// The loop length in samples of the longest comb at the current size
+    fn longest_delay(&self) -> f32 {
+        let buffer_len = self.combs
+            .iter()
+            .map(|c| c.0.buffer_len.max(c.1.buffer_len))
+            .max()
+            .unwrap_or(0);
+        (buffer_len * self.size / 100) as f32
+    }
+
     #[warn(dead_code)]
     pub fn freeze(&mut self, is_freeze: bool) {
         self.freeze = is_freeze;
         self.update();
     }
 
-    /// Sets the current buffer ID
-    pub fn set_idx(&mut self, idx: usize) {
-        self.buffer_data.buffer_idx = idx;
+    /// Sets how much of the pitch shifted signal is fed back into the combs
+    pub fn shimmer_amount(&mut self, amount: f32) {
+        self.shimmer_amount = amount;
+        if amount <= 0.0 {
+            self.shimmer_feedback = (0.0, 0.0);
+        }
+    }
+
+    /// Sets the shimmer pitch shift in semitones
+    pub fn shimmer_pitch(&mut self, semitones: f32) {
+        if semitones != self.shimmer_pitch {
+            self.shimmer_pitch = semitones;
+            self.shifters.0.set_semitones(semitones);
+            self.shifters.1.set_semitones(semitones);
+        }
     }
 
-    /// Sets the current buffer size
-    pub fn set_size(&mut self, size: usize) {
-        self.buffer_data.buffer_size = size;
+    /// Sets the damping of the shimmer feedback
+    pub fn shimmer_tone(&mut self, tone: f32) {
+        self.shimmer_tone.0.set_damp(tone * 0.9);
+        self.shimmer_tone.1.set_damp(tone * 0.9);
+    }
+
+    /// Sets the frequency shift of the left and right comb feedback in Hz
+    pub fn freq_shift(&mut self, left: f32, right: f32) {
+        if (left, right) != self.freq_shift {
+            self.freq_shift = (left, right);
+            self.update_freq_shift();
+        }
+    }
+
+    /// Turns the frequency shifters in the comb feedback on or off
+    pub fn freq_shift_bypass(&mut self, bypass: bool) {
+        if bypass != self.freq_shift_bypass {
+            self.freq_shift_bypass = bypass;
+            self.update_freq_shift();
+        }
+    }
+
+    fn update_freq_shift(&mut self) {
+        let (left, right) = if self.freq_shift_bypass {
+            (0.0, 0.0)
+        } else {
+            (self.freq_shift.0 / self.sample_rate, self.freq_shift.1 / self.sample_rate)
+        };
+
+        for comb in self.combs.iter_mut() {
+            comb.0.set_shift(left);
+            comb.1.set_shift(right);
+        }
+    }
+
+    /// Turns the compressor on the output on or off
+    pub fn set_compress(&mut self, compress: bool) {
+        if compress != self.compress {
+            self.compress = compress;
+            self.compressor.reset();
+        }
+    }
+
+    /// The compressor on the output, for setting its parameters
+    pub fn compressor(&mut self) -> &mut Compressor {
+        &mut self.compressor
+    }
+
+    /// Sets the saturation drive in the comb feedback in dB, 0.0 turns it off
+    pub fn set_drive(&mut self, drive: f32) {
+        for comb in self.combs.iter_mut() {
+            comb.0.saturator().set_drive(drive);
+            comb.1.saturator().set_drive(drive);
+        }
+    }
+
+    /// Sets the curve of the saturation in the comb feedback
+    pub fn set_drive_curve(&mut self, curve: Curve) {
+        for comb in self.combs.iter_mut() {
+            comb.0.saturator().set_curve(curve);
+            comb.1.saturator().set_curve(curve);
+        }
     }
 
     /// Sets the bit rate of the bit crusher
     pub fn set_bit_rate(&mut self, bit_rate: f32) {
-        self.bitcrusher.set_bit_rate(bit_rate);
+        for crusher in self.crushers() {
+            crusher.set_bit_rate(bit_rate);
+        }
+    }
+
+    /// Sets the downsampling factor of the bit crusher
+    pub fn set_downsample(&mut self, factor: f32) {
+        self.downsample = factor;
+        self.update_downsample();
+    }
+
+    /// The crushers run at the oversampled rate, so they hold every sample for
+    /// that much longer
+    fn update_downsample(&mut self) {
+        let factor = self.downsample * self.oversamplers.0.factor() as f32;
+        for crusher in self.crushers() {
+            crusher.set_sample_rate(factor);
+        }
+    }
+
+    /// Sets the oversampling factor of the bit crusher, 1, 2, 4 or 8, and the
+    /// filters used for it
+    pub fn set_oversampling(&mut self, factor: usize, filter: Filter) {
+        let oversamplers = [&mut self.oversamplers.0, &mut self.oversamplers.1]
+            .into_iter()
+            .chain(self.combs.iter_mut().flat_map(|c| [c.0.oversampler(), c.1.oversampler()]));
+        for oversampler in oversamplers {
+            oversampler.set_factor(factor);
+            oversampler.set_filter(filter);
+        }
+        self.update_downsample();
+    }
+
+    /// Returns the latency the oversampling adds to the output in samples. In
+    /// the feedback path it is taken off the comb delays instead.
+    pub fn latency(&self) -> usize {
+        if self.crush_placement == Placement::Loop {
+            0
+        } else {
+            self.oversamplers.0.latency()
+        }
+    }
+
+    /// Turns the anti-aliasing filter of the bit crusher on or off
+    pub fn set_anti_alias(&mut self, anti_alias: bool) {
+        for crusher in self.crushers() {
+            crusher.set_anti_alias(anti_alias);
+        }
+    }
+
+    /// Sets the level of the noise added in the bit crusher
+    pub fn set_noise_level(&mut self, level: f32) {
+        for crusher in self.crushers() {
+            crusher.set_noise_level(level);
+        }
+    }
+
+    /// Sets the dither of the bit crusher
+    pub fn set_dither(&mut self, dither: Dither) {
+        for crusher in self.crushers() {
+            crusher.set_dither(dither);
+        }
+    }
+
+    /// Sets the quantizer curve of the bit crusher
+    pub fn set_quantizer(&mut self, quantizer: Quantizer) {
+        for crusher in self.crushers() {
+            crusher.set_quantizer(quantizer);
+        }
+    }
+
+    /// Sets where the bit crusher sits
+    pub fn set_crush_placement(&mut self, placement: Placement) {
+        if placement != self.crush_placement {
+            self.crush_placement = placement;
+            self.update_crush_mix();
+        }
+    }
+
+    /// Sets how much of the crushed signal is used, from 0.0 to 1.0
+    pub fn set_crush_mix(&mut self, mix: f32) {
+        if mix != self.crush_mix {
+            self.crush_mix = mix;
+            self.update_crush_mix();
+        }
+    }
+
+    fn update_crush_mix(&mut self) {
+        let mix = if self.crush_placement == Placement::Loop { self.crush_mix } else { 0.0 };
+        for comb in self.combs.iter_mut() {
+            comb.0.set_crush_mix(mix);
+            comb.1.set_crush_mix(mix);
+        }
+    }
+
+    /// Every bit crusher, the two on the input or output and the ones in the combs
+    fn crushers(&mut self) -> impl Iterator<Item = &mut BitCrusher> {
+        [&mut self.bitcrusher.0, &mut self.bitcrusher.1]
+            .into_iter()
+            .chain(self.combs.iter_mut().flat_map(|c| [c.0.crusher(), c.1.crusher()]))
     }
 }
 
-impl BufferData {
-    pub fn new() -> Self {
-        BufferData {
-            buffer_idx: 0, 
-            buffer_size: 0,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    /// Renders noise through the reverb with the bit crusher downsampling by a
+    /// fractional factor, setting the parameters at the start of every block
+    /// like the plugin does.
+    fn render(block_size: usize) -> Vec<(f32, f32)> {
+        let mut reverb = Reverb::new(44100);
+        let mut random = Random::new(7);
+        let mut output = Vec::new();
+
+        for _ in 0..8192 / block_size {
+            reverb.size(75);
+            reverb.set_bit_rate(8.0);
+            reverb.set_downsample(3.7);
+            reverb.set_anti_alias(true);
+            for _ in 0..block_size {
+                let x = random.next_f32() - 0.5;
+                output.push(reverb.process((x, -x)));
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn bit_crusher_ignores_block_size() {
+        let small = render(64);
+        let large = render(2048);
+        assert_eq!(small.len(), large.len());
+        for (a, b) in small.iter().zip(&large) {
+            assert_eq!(a.0.to_bits(), b.0.to_bits());
+            assert_eq!(a.1.to_bits(), b.1.to_bits());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn loop_crusher_tail_dies_out() {
+        // Rounding in the loop can hold a tail at a level where every pass
+        // rounds back up to the same step, so it would ring forever
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        reverb.decay(0.5);
+        reverb.set_crush_placement(Placement::Loop);
+        reverb.set_bit_rate(8.0);
+        let mut random = Random::new(3);
+
+        for _ in 0..22050 {
+            let x = random.next_f32() * 2.0 - 1.0;
+            reverb.process((x, -x));
+        }
+        let tail = (4.0 * reverb.decay_time() * 44100.0) as usize;
+        let peak = (0..tail)
+            .map(|_| reverb.process((0.0, 0.0)))
+            .skip(tail - 4410)
+            .fold(0.0_f32, |peak, out| peak.max(out.0.abs()).max(out.1.abs()));
+
+        assert!(peak < 10.0_f32.powf(-90.0 / 20.0), "tail at {} dB", 20.0 * peak.log10());
+    }
+
+    #[test]
+    fn crusher_noise_differs_between_channels() {
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        reverb.set_noise_level(0.5);
+
+        let same = (0..4410)
+            .map(|_| reverb.process((0.0, 0.0)))
+            .filter(|out| out.0 == out.1)
+            .count();
+        assert!(same < 441, "{} of 4410 samples are the same", same);
+    }
+
+    #[test]
+    fn right_input_alone_is_heard() {
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        let (mut left, mut right) = (0.0_f32, 0.0_f32);
+
+        for n in 0..44100 {
+            let x = if n == 0 { 1.0 } else { 0.0 };
+            let out = reverb.process((0.0, x));
+            left = left.max(out.0.abs());
+            right = right.max(out.1.abs());
+        }
+
+        assert!(left > 0.01, "left {}", left);
+        assert!(right > 0.01, "right {}", right);
+    }
+
+    /// The peak output of every second of a reverb with full shimmer, after a
+    /// short burst of noise
+    fn shimmer_peaks(decay: f32, pitch: f32, freeze: bool) -> Vec<f32> {
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        reverb.decay(decay);
+        reverb.freeze(freeze);
+        reverb.shimmer_amount(1.0);
+        reverb.shimmer_pitch(pitch);
+        reverb.shimmer_tone(0.0);
+        // The default 6 bits would round the quiet end of the tail to silence
+        reverb.set_crush_mix(0.0);
+        let mut random = Random::new(5);
+
+        (0..6)
+            .map(|second| {
+                let mut peak: f32 = 0.0;
+                for n in 0..44100 {
+                    let x = if second == 0 && n < 11025 { random.next_f32() - 0.5 } else { 0.0 };
+                    let (left, right) = reverb.process((x, x));
+                    peak = peak.max(left.abs()).max(right.abs());
+                }
+                peak
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shimmer_stays_bounded() {
+        // An unshifted shimmer is plain extra feedback, the hardest case
+        for pitch in [12.0, 0.0, -0.5] {
+            for decay in [0.0, 1.0] {
+                let peaks = shimmer_peaks(decay, pitch, false);
+                assert!(peaks[5] < peaks[1], "pitch {pitch}, decay {decay}: {peaks:?}");
+            }
+
+            // Frozen, the tail holds but doesn't grow
+            let peaks = shimmer_peaks(1.0, pitch, true);
+            assert!(peaks.iter().all(|peak| *peak < 8.0), "pitch {pitch}: {peaks:?}");
+            assert!(peaks[5] < 1.5 * peaks[1], "pitch {pitch}: {peaks:?}");
+        }
+    }
+}
@@ -0,0 +1,236 @@
+// Protection against the reverb blowing up. A NaN or an infinity that gets into
+// a feedback loop stays there forever, and a loop with too much gain grows
+// until it is one. The peak level of every comb delay line is followed, and the
+// output is checked on every sample. Whatever trips the check is reset and the
+// output fades back in. The plugin goes through `Guard` for all of this, so the
+// tests here run the same checks it does.
+
+/// The highest level that is still taken as a signal, +60 dBFS
+pub const MAX_LEVEL: f32 = 1000.0;
+
+// How long the output takes to fade back in after a reset, in ms
+const RECOVERY: f32 = 50.0;
+
+/// Returns true when `x` is finite and not louder than `MAX_LEVEL`.
+pub fn is_sane(x: f32) -> bool {
+    // Also false for NaN
+    x.abs() <= MAX_LEVEL
+}
+
+/// Follows the peak level of a signal. A NaN sticks until the next check.
+pub struct Peak {
+    peak: f32,
+}
+
+impl Default for Peak {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Peak {
+    pub fn new() -> Self {
+        Self { peak: 0.0 }
+    }
+
+    pub fn process(&mut self, x: f32) {
+        let level = x.abs();
+        if level.is_nan() || level > self.peak {
+            self.peak = level;
+        }
+    }
+
+    /// Returns whether everything since the last check was sane, and starts over
+    pub fn check(&mut self) -> bool {
+        let sane = is_sane(self.peak);
+        self.peak = 0.0;
+        sane
+    }
+}
+
+/// What made the reverb blow up in a block.
+pub struct Fault {
+    pub cause: &'static str,
+    /// Only true for the first fault until the output is back, so every
+    /// incident is only reported once
+    pub report: bool,
+}
+
+/// Silences the reverb once it blew up, and mutes the output after the reset
+/// and fades it back in.
+pub struct Guard {
+    gain: f32,
+    step: f32,
+    // The fault in the current block, if there is one
+    fault: Option<Fault>,
+}
+
+impl Guard {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        Self {
+            gain: 1.0,
+            step: 1.0 / (RECOVERY * 0.001 * sample_rate as f32),
+            fault: None,
+        }
+    }
+
+    /// Mutes the output, returns true when it had fully faded back in since
+    /// the last time, so every incident is only reported once
+    pub fn trip(&mut self) -> bool {
+        let recovered = self.gain >= 1.0;
+        self.gain = 0.0;
+        recovered
+    }
+
+    /// Returns the `wet` output of the engine, or silence once it wasn't sane.
+    /// Once it blew up the rest of the block is garbage, so it stays silent.
+    pub fn check(&mut self, wet: (f32, f32)) -> (f32, f32) {
+        if self.fault.is_none() && !(is_sane(wet.0) && is_sane(wet.1)) {
+            self.fail("a non-finite or runaway output");
+        }
+        if self.fault.is_some() { (0.0, 0.0) } else { wet }
+    }
+
+    /// Returns true once the engine blew up in this block, anything else it
+    /// puts out has to be silenced as well
+    pub fn failed(&self) -> bool {
+        self.fault.is_some()
+    }
+
+    /// Ends the block, `stable` tells whether every delay line stayed sane.
+    /// Returns the fault when there was one, the engine has to be reset then.
+    pub fn end_block(&mut self, stable: bool) -> Option<Fault> {
+        if !stable && self.fault.is_none() {
+            self.fail("a runaway delay line");
+        }
+        self.fault.take()
+    }
+
+    fn fail(&mut self, cause: &'static str) {
+        let report = self.trip();
+        self.fault = Some(Fault { cause, report });
+    }
+
+    /// Returns the gain for the next sample
+    pub fn next_gain(&mut self) -> f32 {
+        self.gain = (self.gain + self.step).min(1.0);
+        self.gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+    use crate::reverb::Reverb;
+
+    const BLOCK: usize = 512;
+
+    /// Renders a block of `input` through `reverb`, guarded the way the plugin
+    /// does it. Returns the output and whether the guard tripped.
+    fn render(reverb: &mut Reverb, guard: &mut Guard, input: &[f32]) -> (Vec<(f32, f32)>, bool) {
+        let output = input
+            .iter()
+            .map(|x| {
+                let (left, right) = guard.check(reverb.process((*x, *x)));
+                let gain = guard.next_gain();
+                (left * gain, right * gain)
+            })
+            .collect();
+
+        let fault = guard.end_block(reverb.is_stable());
+        if fault.is_some() {
+            reverb.reset();
+        }
+        (output, fault.is_some())
+    }
+
+    fn reverb() -> Reverb {
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        reverb
+    }
+
+    fn noise(random: &mut Random) -> Vec<f32> {
+        (0..BLOCK).map(|_| random.next_f32() - 0.5).collect()
+    }
+
+    fn recovers_from(fault: f32) {
+        let mut reverb = reverb();
+        let mut guard = Guard::new(44100);
+        let mut random = Random::new(3);
+
+        let (_, tripped) = render(&mut reverb, &mut guard, &noise(&mut random));
+        assert!(!tripped);
+
+        let mut input = noise(&mut random);
+        input[100] = fault;
+        let (output, tripped) = render(&mut reverb, &mut guard, &input);
+        assert!(tripped);
+        assert!(output.iter().all(|(l, r)| is_sane(*l) && is_sane(*r)));
+
+        // From the very next block on it is clean again, and sounds exactly like
+        // a reverb that never saw the fault, fading back in. The tail only
+        // starts after the shortest comb.
+        let mut fresh = self::reverb();
+        let mut peak: f32 = 0.0;
+        for block in 0..8 {
+            let input = noise(&mut random);
+            let (output, tripped) = render(&mut reverb, &mut guard, &input);
+            assert!(!tripped);
+            for (i, (x, (left, right))) in input.iter().zip(&output).enumerate() {
+                let n = block * BLOCK + i;
+                let gain = ((n + 1) as f32 / (RECOVERY * 0.001 * 44100.0)).min(1.0);
+                let expected = fresh.process((*x, *x));
+                assert!((left - expected.0 * gain).abs() < 1e-5, "sample {}", n);
+                assert!((right - expected.1 * gain).abs() < 1e-5, "sample {}", n);
+                peak = peak.max(left.abs());
+            }
+        }
+        assert!(peak > 1e-2, "peak {}", peak);
+    }
+
+    #[test]
+    fn recovers_from_nan() {
+        recovers_from(f32::NAN);
+    }
+
+    #[test]
+    fn recovers_from_infinity() {
+        recovers_from(f32::INFINITY);
+    }
+
+    #[test]
+    fn recovers_from_huge_values() {
+        recovers_from(1e30);
+    }
+
+    #[test]
+    fn fault_lasts_until_the_end_of_the_block() {
+        let mut guard = Guard::new(44100);
+        assert_eq!(guard.check((0.5, 0.5)), (0.5, 0.5));
+        assert!(!guard.failed());
+        guard.check((f32::NAN, 0.0));
+        assert!(guard.failed());
+        assert_eq!(guard.check((0.5, 0.5)), (0.0, 0.0));
+        assert!(guard.end_block(true).is_some());
+        assert!(!guard.failed());
+    }
+
+    #[test]
+    fn guard_fades_back_in() {
+        let mut guard = Guard::new(1000);
+        assert!(guard.trip());
+        // Tripping again before it is back is the same incident
+        assert!(!guard.trip());
+        // 50 ms at 1 kHz, with a little room for rounding
+        let gains: Vec<f32> = (0..52).map(|_| guard.next_gain()).collect();
+        assert!(gains[0] < 0.1);
+        assert!(gains.windows(2).all(|g| g[1] >= g[0]));
+        assert_eq!(gains[51], 1.0);
+        assert!(guard.trip());
+    }
+}
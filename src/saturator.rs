@@ -0,0 +1,160 @@
+// Saturation for the comb feedback. The curves are run with first order
+// antiderivative anti-aliasing: instead of the curve itself, the slope of its
+// antiderivative between the last two samples is used, which takes out most of
+// the aliasing without oversampling and without adding latency to the loop.
+//
+// Every curve has a slope of one around zero, so quiet signals pass unchanged
+// and the decay stays the same. The output is scaled back down by the drive,
+// which caps how loud the recirculating signal can get.
+
+use std::f64::consts::LN_2;
+
+// Below this difference between two samples the slope of the antiderivative is
+// too imprecise, the curve is used at the midpoint instead
+const ADAA_EPSILON: f64 = 1e-5;
+// Where the cubic of the soft clip curve reaches full scale
+const SOFT_CLIP_KNEE: f64 = 1.5;
+// Offset of the tube curve, which makes it clip the positive half earlier
+const TUBE_BIAS: f64 = 0.3;
+// Pole of the DC blocker after the tube curve, the cutoff is a few Hz
+const DC_POLE: f32 = 0.9995;
+
+/// The shape of the saturation.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Curve {
+    /// Smooth and symmetric
+    Tanh,
+    /// A cubic up to full scale and flat above, stays cleaner longer
+    SoftClip,
+    /// Asymmetric, which adds even harmonics
+    Tube,
+    /// Rounds off early and slowly, like tape
+    Tape,
+}
+
+impl Curve {
+    fn shape(self, x: f64) -> f64 {
+        match self {
+            Curve::Tanh => x.tanh(),
+            Curve::SoftClip => {
+                if x.abs() < SOFT_CLIP_KNEE {
+                    x - 4.0 / 27.0 * x * x * x
+                } else {
+                    x.signum()
+                }
+            }
+            Curve::Tube => {
+                let bias = TUBE_BIAS.tanh();
+                ((x + TUBE_BIAS).tanh() - bias) / (1.0 - bias * bias)
+            }
+            Curve::Tape => x.signum() * (1.0 - (-x.abs()).exp()),
+        }
+    }
+
+    /// The antiderivative of `shape()`
+    fn antiderivative(self, x: f64) -> f64 {
+        match self {
+            Curve::Tanh => log_cosh(x),
+            Curve::SoftClip => {
+                if x.abs() < SOFT_CLIP_KNEE {
+                    0.5 * x * x - x * x * x * x / 27.0
+                } else {
+                    // Continues from the value of the cubic at the knee
+                    x.abs() - 0.5625
+                }
+            }
+            Curve::Tube => {
+                let bias = TUBE_BIAS.tanh();
+                (log_cosh(x + TUBE_BIAS) - x * bias) / (1.0 - bias * bias)
+            }
+            Curve::Tape => x.abs() + (-x.abs()).exp() - 1.0,
+        }
+    }
+}
+
+/// ln(cosh(x)) without overflowing for large `x`
+fn log_cosh(x: f64) -> f64 {
+    let x = x.abs();
+    x + (-2.0 * x).exp().ln_1p() - LN_2
+}
+
+pub struct Saturator {
+    curve: Curve,
+    // Drive in dB and the same as a linear gain before the curve, the output
+    // is divided by it again
+    drive: f32,
+    gain: f32,
+    // The last input after the gain and its antiderivative
+    prev: (f64, f64),
+    // Last input and output of the DC blocker
+    dc_state: (f32, f32),
+}
+
+impl Default for Saturator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Saturator {
+    pub fn new() -> Self {
+        Self {
+            curve: Curve::Tanh,
+            drive: 0.0,
+            gain: 1.0,
+            prev: (0.0, 0.0),
+            dc_state: (0.0, 0.0),
+        }
+    }
+
+    /// Sets the drive in dB, 0.0 turns the saturation off
+    pub fn set_drive(&mut self, drive: f32) {
+        let drive = drive.max(0.0);
+        if drive != self.drive {
+            self.drive = drive;
+            let gain = 10.0_f32.powf(drive / 20.0);
+            // Keep the last input where it was before the gain
+            let x = self.prev.0 * (gain / self.gain) as f64;
+            self.prev = (x, self.curve.antiderivative(x));
+            self.gain = gain;
+        }
+    }
+
+    pub fn set_curve(&mut self, curve: Curve) {
+        if curve != self.curve {
+            self.curve = curve;
+            self.prev = (self.prev.0, curve.antiderivative(self.prev.0));
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.gain > 1.0
+    }
+
+    pub fn reset(&mut self) {
+        self.prev = (0.0, 0.0);
+        self.dc_state = (0.0, 0.0);
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let x = (x * self.gain) as f64;
+        let antiderivative = self.curve.antiderivative(x);
+
+        let y = if (x - self.prev.0).abs() > ADAA_EPSILON {
+            (antiderivative - self.prev.1) / (x - self.prev.0)
+        } else {
+            self.curve.shape(0.5 * (x + self.prev.0))
+        };
+        self.prev = (x, antiderivative);
+
+        let y = y as f32 / self.gain;
+        if self.curve != Curve::Tube {
+            return y;
+        }
+
+        // The asymmetry adds an offset, which would build up in the loop
+        let output = y - self.dc_state.0 + self.dc_state.1 * DC_POLE;
+        self.dc_state = (y, output);
+        output
+    }
+}
@@ -0,0 +1,228 @@
+// Spreads the stereo reverb over the channels of the surround layouts. The
+// front pair gets the tail as it is, every other channel runs it through its own
+// chain of allpasses. The chains use the same tunings, each spread by a different
+// multiple of OFFSET like the right channel of the reverb, so every channel gets
+// a tail with the same decay that is decorrelated from all the others.
+//
+// The extra channels don't get comb sets of their own. Those would only exist
+// for the comb engines, while this way every engine, the convolution included,
+// sounds the same on every speaker. Unlike the Freeverb allpasses these are true
+// allpasses, they pass hardly any of the tail through unchanged, so folding the
+// speakers down to stereo doesn't comb filter it.
+
+use crate::delay_buffer::DelayBuffer;
+use crate::one_pole::OnePole;
+
+const OFFSET: usize = 23;
+
+const AP_TUN_1: usize = 142;
+const AP_TUN_2: usize = 107;
+const AP_TUN_3: usize = 379;
+const AP_TUN_4: usize = 277;
+
+// Feedback of the allpasses, the part of the input they pass through right away
+const AP_GAIN: f32 = 0.5;
+
+// Cutoff of the lowpass on the LFE send in Hz
+const LFE_CUTOFF: f32 = 120.0;
+
+/// A chain of allpasses that decorrelates a signal without changing its
+/// spectrum or decay.
+pub struct Decorrelator {
+    delays: [DelayBuffer; 4],
+}
+
+impl Decorrelator {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    /// * `spread` - how many times OFFSET is added to the tunings, decorrelators
+    ///   that should not correlate with each other need different values
+    pub fn new(sample_rate: usize, spread: usize) -> Self {
+        let len = |tuning: usize| (tuning + spread * OFFSET) * sample_rate / 44100;
+
+        Self {
+            delays: [
+                DelayBuffer::new(len(AP_TUN_1)),
+                DelayBuffer::new(len(AP_TUN_2)),
+                DelayBuffer::new(len(AP_TUN_3)),
+                DelayBuffer::new(len(AP_TUN_4)),
+            ],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for delay in self.delays.iter_mut() {
+            delay.reset();
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.delays.iter_mut().fold(input, |out, delay| {
+            let delayed = delay.read();
+            let feedback = out + delayed * AP_GAIN;
+            delay.write(feedback);
+            delay.advance();
+            delayed - feedback * AP_GAIN
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Speaker {
+    Left,
+    Right,
+    Center,
+    Lfe,
+    RearLeft,
+    RearRight,
+    SideLeft,
+    SideRight,
+}
+
+/// The speakers of a layout with `channels` outputs, in the WAVE_FORMAT_EXTENSIBLE
+/// order that VST3 and CLAP use as well. Anything that isn't quad, 5.1 or 7.1
+/// is treated as stereo.
+fn speakers(channels: usize) -> &'static [Speaker] {
+    use Speaker::*;
+
+    match channels {
+        4 => &[Left, Right, RearLeft, RearRight],
+        6 => &[Left, Right, Center, Lfe, RearLeft, RearRight],
+        8 => &[Left, Right, Center, Lfe, RearLeft, RearRight, SideLeft, SideRight],
+        _ => &[Left, Right],
+    }
+}
+
+pub struct Surround {
+    speakers: &'static [Speaker],
+    // One decorrelator per speaker, the front pair doesn't use theirs
+    decorrelators: Vec<Decorrelator>,
+    // Two poles for a steeper slope on the LFE send
+    lfe_filter: (OnePole, OnePole),
+    // The last output, one sample per speaker
+    frame: Vec<f32>,
+    front: f32,
+    rear: f32,
+    center: f32,
+    lfe: f32,
+}
+
+impl Surround {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    /// * `channels` - the number of output channels
+    pub fn new(sample_rate: usize, channels: usize) -> Self {
+        let speakers = speakers(channels);
+        let lfe_damp = (-2.0 * std::f32::consts::PI * LFE_CUTOFF / sample_rate as f32).exp();
+        let mut lfe_filter = (OnePole::new(), OnePole::new());
+        lfe_filter.0.set_damp(lfe_damp);
+        lfe_filter.1.set_damp(lfe_damp);
+
+        Self {
+            speakers,
+            decorrelators: (0..speakers.len())
+                .map(|spread| Decorrelator::new(sample_rate, spread))
+                .collect(),
+            lfe_filter,
+            frame: vec![0.0; speakers.len()],
+            front: 1.0,
+            rear: 1.0,
+            center: 0.5,
+            lfe: 0.0,
+        }
+    }
+
+    /// Sets the balance between the front and the rear speakers, from -1.0 for
+    /// the front only to 1.0 for the rear only. The side speakers are in between.
+    pub fn balance(&mut self, balance: f32) {
+        self.front = (1.0 - balance).min(1.0);
+        self.rear = (1.0 + balance).min(1.0);
+    }
+
+    /// Sets the level of the center speaker
+    pub fn center(&mut self, level: f32) {
+        self.center = level;
+    }
+
+    /// Sets the level of the LFE send
+    pub fn lfe(&mut self, level: f32) {
+        self.lfe = level;
+    }
+
+    pub fn reset(&mut self) {
+        for decorrelator in self.decorrelators.iter_mut() {
+            decorrelator.reset();
+        }
+        self.lfe_filter.0.reset();
+        self.lfe_filter.1.reset();
+        self.frame.fill(0.0);
+    }
+
+    /// Spreads the stereo `wet` signal over the speakers and returns one sample
+    /// per output channel.
+    pub fn process(&mut self, wet: (f32, f32)) -> &[f32] {
+        let mid = (wet.0 + wet.1) * 0.5;
+        let side = (self.front + self.rear) * 0.5;
+
+        for ((out, speaker), decorrelator) in self.frame
+            .iter_mut()
+            .zip(self.speakers)
+            .zip(self.decorrelators.iter_mut())
+        {
+            *out = match speaker {
+                Speaker::Left => wet.0 * self.front,
+                Speaker::Right => wet.1 * self.front,
+                Speaker::Center => decorrelator.process(mid) * self.center,
+                Speaker::Lfe => self.lfe_filter.1.process(self.lfe_filter.0.process(mid)) * self.lfe,
+                Speaker::RearLeft => decorrelator.process(wet.0) * self.rear,
+                Speaker::RearRight => decorrelator.process(wet.1) * self.rear,
+                Speaker::SideLeft => decorrelator.process(wet.0) * side,
+                Speaker::SideRight => decorrelator.process(wet.1) * side,
+            };
+        }
+
+        &self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+    use crate::reverb::Reverb;
+
+    /// The correlation of `a` and `b`, from -1 to 1
+    fn correlation(a: &[f32], b: &[f32]) -> f32 {
+        let dot = |x: &[f32], y: &[f32]| x.iter().zip(y).map(|(x, y)| x * y).sum::<f32>();
+        dot(a, b) / (dot(a, a) * dot(b, b)).sqrt()
+    }
+
+    #[test]
+    fn speakers_fold_down_without_correlation() {
+        let mut reverb = Reverb::new(44100);
+        reverb.size(75);
+        let mut surround = Surround::new(44100, 8);
+        let mut random = Random::new(3);
+        let mut channels = vec![Vec::new(); 8];
+
+        for n in 0..44100 * 2 {
+            let x = if n < 4410 { random.next_f32() - 0.5 } else { 0.0 };
+            let wet = reverb.process((x, x));
+            for (channel, sample) in channels.iter_mut().zip(surround.process(wet)) {
+                channel.push(*sample);
+            }
+        }
+
+        // Every speaker on the left side against the front left, and the same on
+        // the right. The LFE is filtered down to almost nothing.
+        for side in [[0, 2, 4, 6], [1, 2, 5, 7]] {
+            for pair in [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)] {
+                let (a, b) = (&channels[side[pair.0]], &channels[side[pair.1]]);
+                let rho = correlation(a, b);
+                assert!(rho.abs() < 0.1, "{:?} {}", pair, rho);
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Keeps track of how much of the reverb tail is left. The input and the output
+// levels are followed with RMS detectors. While there is input the full tail is
+// still ahead, once the input is silent the tail counts down, and it ends early
+// when the output has decayed below the silence threshold.
+
+// Mean square level below which a signal counts as silent, about -90 dBFS
+const SILENCE: f32 = 1e-9;
+// Averaging time of the RMS detectors in ms
+const RMS_TIME: f32 = 50.0;
+
+pub struct TailDetector {
+    sample_rate: f32,
+    coef: f32,
+    input_level: f32,
+    output_level: f32,
+    // Number of samples the input has been silent for
+    silent_samples: usize,
+}
+
+impl TailDetector {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        Self {
+            sample_rate,
+            coef: (-1.0 / (RMS_TIME * 0.001 * sample_rate)).exp(),
+            input_level: 0.0,
+            output_level: 0.0,
+            silent_samples: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.input_level = 0.0;
+        self.output_level = 0.0;
+        self.silent_samples = 0;
+    }
+
+    /// Follows the plugin's `input` and the reverb `output`.
+    pub fn process(&mut self, input: (f32, f32), output: (f32, f32)) {
+        let input_square = (input.0 * input.0 + input.1 * input.1) * 0.5;
+        let output_square = (output.0 * output.0 + output.1 * output.1) * 0.5;
+        self.input_level = input_square + (self.input_level - input_square) * self.coef;
+        self.output_level = output_square + (self.output_level - output_square) * self.coef;
+
+        if self.input_level < SILENCE {
+            self.silent_samples += 1;
+        } else {
+            self.silent_samples = 0;
+        }
+    }
+
+    /// Returns the remaining tail in samples for a reverb that takes
+    /// `tail_time` seconds to die out, or `None` if the tail never ends.
+    pub fn remaining(&self, tail_time: f32) -> Option<u32> {
+        if !tail_time.is_finite() {
+            return None;
+        }
+
+        let tail = (tail_time * self.sample_rate) as usize;
+        let remaining = if self.silent_samples == 0 {
+            tail
+        } else if self.output_level < SILENCE {
+            0
+        } else {
+            // Things like shimmer can make the tail outlast the decay time, so
+            // as long as the output is audible there is always a bit left
+            let minimum = (RMS_TIME * 0.001 * self.sample_rate) as usize;
+            tail.saturating_sub(self.silent_samples).max(minimum)
+        };
+
+        Some(remaining.min(u32::MAX as usize) as u32)
+    }
+}
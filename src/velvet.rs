@@ -0,0 +1,453 @@
+// A sparse FIR reverb based on velvet noise. Velvet noise is a sequence of
+// randomly placed +1/-1 impulses, one per grid cell, with silence in between.
+// Giving the impulses an exponential envelope turns the sequence into a smooth,
+// colorless reverb tail, and since most of the taps are zero only the non-zero
+// ones need to be evaluated.
+//
+// Other envelopes turn it into a nonlinear reverb, like a reverse swell or a
+// flat gated tail.
+//
+// Every tap costs a read from the input history, so the sequence gets sparser
+// further into the tail, where the damping smooths it out anyway. The grid gets
+// twice as coarse every THIN_TIME seconds, and the taps louder to keep the
+// energy the same. New sequences are generated a few taps per sample and then
+// crossfaded in, so automation doesn't click or stall the audio thread.
+
+use crate::one_pole::OnePole;
+use crate::random::Random;
+
+// The longest sequence we support, in seconds
+const MAX_LENGTH: f32 = 4.0;
+
+// Tap density in impulses per second, for every percent of size
+const DENSITY_PER_SIZE: f32 = 20.0;
+const MAX_DENSITY: f32 = 100.0 * DENSITY_PER_SIZE;
+
+// The grid gets twice as coarse every this many seconds...
+const THIN_TIME: f32 = 0.1;
+// ...until the density is down to this many impulses per second
+const MIN_DENSITY: f32 = 200.0;
+
+// Taps generated per sample while a new sequence is being built
+const GENERATE_STEP: usize = 32;
+// Length of the crossfade to a new sequence in ms
+const FADE_TIME: f32 = 20.0;
+
+// XORed into the seed of the right sequence so it is decorrelated from the left
+const RIGHT_SEED: u32 = 0x5EED_0001;
+
+/// The most breakpoints a custom envelope can have, one for each pair of
+/// breakpoint params
+pub const MAX_POINTS: usize = 4;
+
+/// Maps the normalized decay parameter to a RT60 decay time in seconds.
+pub fn decay_time(decay: f32) -> f32 {
+    0.3 + decay * (MAX_LENGTH - 0.3)
+}
+
+/// The envelope applied to the impulses.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Envelope {
+    /// Decays by 60 dB over the decay time
+    Exponential,
+    /// Swells up by 60 dB over the length, then stops
+    Reverse,
+    /// Stays flat for the length, then stops
+    Gated,
+    /// Follows the breakpoints set with `points()` over the length
+    Custom,
+}
+
+struct Tap {
+    delay: usize,
+    gain: f32,
+}
+
+/// Where the generation of a new pair of sequences is at.
+struct Generator {
+    random: (Random, Random),
+    // Length of the sequences in samples
+    length: f32,
+    // Start of the next grid cell in samples
+    start: f32,
+    energy: (f32, f32),
+}
+
+pub struct VelvetReverb {
+    sample_rate: f32,
+    // Input history, its length is a power of two so it can be wrapped with a mask
+    history: Vec<f32>,
+    mask: usize,
+    write_pos: usize,
+    // Non-zero taps for the left and right sequences
+    taps: (Vec<Tap>, Vec<Tap>),
+    // The sequences being generated, and then faded in
+    pending: (Vec<Tap>, Vec<Tap>),
+    generator: Option<Generator>,
+    // Samples left in the crossfade to the pending sequences
+    fade: usize,
+    fade_len: usize,
+    // Post filters used for damping
+    filters: (OnePole, OnePole),
+    wet_gains: (f32, f32),
+    wet: f32,
+    width: f32,
+    decay: f32,
+    size: usize,
+    seed: u32,
+    envelope: Envelope,
+    // Length in seconds for everything but the exponential envelope
+    length: f32,
+    // Breakpoints of the custom envelope as (position, gain), both from 0.0 to 1.0
+    points: [(f32, f32); MAX_POINTS],
+    num_points: usize,
+    // Set whenever the taps have to be generated again
+    dirty: bool,
+}
+
+impl VelvetReverb {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let sr = sample_rate as f32;
+        let max_len = (MAX_LENGTH * sr) as usize;
+        let history_len = (max_len + 1).next_power_of_two();
+        let max_taps = max_taps(sr);
+
+        let mut velvet = VelvetReverb {
+            sample_rate: sr,
+            history: vec![0.0; history_len],
+            mask: history_len - 1,
+            write_pos: 0,
+            taps: (Vec::with_capacity(max_taps), Vec::with_capacity(max_taps)),
+            pending: (Vec::with_capacity(max_taps), Vec::with_capacity(max_taps)),
+            generator: None,
+            fade: 0,
+            fade_len: (FADE_TIME * 0.001 * sr) as usize,
+            filters: (OnePole::new(), OnePole::new()),
+            wet_gains: (0.0, 0.0),
+            wet: 0.8,
+            width: 1.0,
+            decay: 0.5,
+            size: 50,
+            seed: 1,
+            envelope: Envelope::Exponential,
+            length: 1.0,
+            points: [(0.0, 0.0); MAX_POINTS],
+            num_points: 0,
+            dirty: true,
+        };
+
+        velvet.update_wet_gains();
+        // There is no audio running yet, so the first sequences are used right away
+        velvet.start_generate();
+        while velvet.generator.is_some() {
+            velvet.generate_step();
+        }
+        std::mem::swap(&mut velvet.taps, &mut velvet.pending);
+        velvet.fade = 0;
+        velvet
+    }
+
+    /// Clears the input history
+    pub fn reset(&mut self) {
+        self.history.fill(0.0);
+        self.write_pos = 0;
+        self.filters.0.reset();
+        self.filters.1.reset();
+    }
+
+    /// Processes the `input`, returns the reverb signal without the dry signal.
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        // A change only starts a new sequence once the last one is faded in
+        if self.dirty && self.generator.is_none() && self.fade == 0 {
+            self.start_generate();
+        }
+        if self.generator.is_some() {
+            self.generate_step();
+        }
+
+        self.history[self.write_pos] = (input.0 + input.1) * 0.5;
+
+        let mut out = (self.convolve(&self.taps.0), self.convolve(&self.taps.1));
+        if self.fade > 0 {
+            let new = (self.convolve(&self.pending.0), self.convolve(&self.pending.1));
+            let gain = self.fade as f32 / self.fade_len as f32;
+            out = (new.0 + (out.0 - new.0) * gain, new.1 + (out.1 - new.1) * gain);
+
+            self.fade -= 1;
+            if self.fade == 0 {
+                std::mem::swap(&mut self.taps, &mut self.pending);
+            }
+        }
+
+        self.write_pos = (self.write_pos + 1) & self.mask;
+
+        // Damping is applied after the convolution
+        out.0 = self.filters.0.process(out.0);
+        out.1 = self.filters.1.process(out.1);
+
+        (
+            out.0 * self.wet_gains.0 + out.1 * self.wet_gains.1,
+            out.1 * self.wet_gains.0 + out.0 * self.wet_gains.1,
+        )
+    }
+
+    /// The output of one sequence for the current input history
+    fn convolve(&self, taps: &[Tap]) -> f32 {
+        taps.iter()
+            .map(|tap| tap.gain * self.history[self.write_pos.wrapping_sub(tap.delay) & self.mask])
+            .sum()
+    }
+
+    /// Starts generating both velvet noise sequences from the current envelope,
+    /// size and seed.
+    fn start_generate(&mut self) {
+        self.pending.0.clear();
+        self.pending.1.clear();
+        self.generator = Some(Generator {
+            random: (Random::new(self.seed), Random::new(self.seed ^ RIGHT_SEED)),
+            length: self.tail_time() * self.sample_rate,
+            start: 0.0,
+            energy: (0.0, 0.0),
+        });
+        self.dirty = false;
+    }
+
+    /// Generates the next few taps of both sequences, and starts the crossfade
+    /// once they are done. This never allocates, the tap vectors are sized for
+    /// the worst case.
+    fn generate_step(&mut self) {
+        let Some(mut generator) = self.generator.take() else {
+            return;
+        };
+        let base_grid = self.sample_rate / (self.size.max(1) as f32 * DENSITY_PER_SIZE);
+
+        for _ in 0..GENERATE_STEP {
+            if generator.start >= generator.length || self.pending.0.len() == self.pending.0.capacity() {
+                self.finish_generate(&generator);
+                return;
+            }
+
+            // Sparser cells are louder, so the energy over time stays the same
+            let grid = grid(base_grid, generator.start, self.sample_rate);
+            let scale = (grid / base_grid).sqrt();
+            let cell = |random: &mut Random| {
+                let delay = (generator.start + random.next_f32() * (grid - 1.0)).round() as usize;
+                let sign = if random.next_f32() < 0.5 { -1.0 } else { 1.0 };
+                let gain = sign * scale * self.envelope_gain(delay as f32 / generator.length);
+                Tap { delay, gain }
+            };
+            let left = cell(&mut generator.random.0);
+            let right = cell(&mut generator.random.1);
+            generator.energy.0 += left.gain * left.gain;
+            generator.energy.1 += right.gain * right.gain;
+            self.pending.0.push(left);
+            self.pending.1.push(right);
+            generator.start += grid;
+        }
+
+        self.generator = Some(generator);
+    }
+
+    /// Normalizes the new sequences so that the level does not depend on decay
+    /// or density, and fades them in.
+    fn finish_generate(&mut self, generator: &Generator) {
+        for (taps, energy) in [
+            (&mut self.pending.0, generator.energy.0),
+            (&mut self.pending.1, generator.energy.1),
+        ] {
+            if energy > 0.0 {
+                let norm = 0.5 / energy.sqrt();
+                for tap in taps.iter_mut() {
+                    tap.gain *= norm;
+                }
+            }
+        }
+        self.fade = self.fade_len.max(1);
+    }
+
+    /// Returns the envelope at `position`, from 0.0 at the first tap to 1.0 at
+    /// the end of the sequence.
+    fn envelope_gain(&self, position: f32) -> f32 {
+        match self.envelope {
+            // -60 dB at the end
+            Envelope::Exponential => 10.0_f32.powf(-3.0 * position),
+            Envelope::Reverse => 10.0_f32.powf(-3.0 * (1.0 - position)),
+            Envelope::Gated => 1.0,
+            Envelope::Custom => {
+                let points = &self.points[..self.num_points];
+                match points.iter().position(|p| p.0 > position) {
+                    None => points.last().map_or(1.0, |p| p.1),
+                    Some(0) => points[0].1,
+                    Some(i) => {
+                        let (a, b) = (points[i - 1], points[i]);
+                        a.1 + (b.1 - a.1) * (position - a.0) / (b.0 - a.0)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns how long the tail lasts in seconds
+    pub fn tail_time(&self) -> f32 {
+        match self.envelope {
+            Envelope::Exponential => decay_time(self.decay),
+            _ => self.length,
+        }
+        .min(MAX_LENGTH)
+    }
+
+    /// Sets the wet signal level
+    pub fn wet(&mut self, wet: f32) {
+        self.wet = wet;
+        self.update_wet_gains();
+    }
+
+    /// Updates the level of the wet and crossfeed signals
+    fn update_wet_gains(&mut self) {
+        self.wet_gains = (
+            self.wet * (self.width / 2.0 + 0.5),
+            self.wet * ((1.0 - self.width) / 2.0),
+        )
+    }
+
+    /// Sets the stereo width
+    pub fn width(&mut self, width: f32) {
+        self.width = width;
+        self.update_wet_gains();
+    }
+
+    /// Sets the damping of the post filter
+    pub fn damp(&mut self, damp: f32) {
+        self.filters.0.set_damp(damp * 0.9);
+        self.filters.1.set_damp(damp * 0.9);
+    }
+
+    /// Sets the decay of the envelope
+    pub fn decay(&mut self, decay: f32) {
+        if decay != self.decay {
+            self.decay = decay;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the tap density, in percent
+    pub fn size(&mut self, size: usize) {
+        if size != self.size {
+            self.size = size;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the envelope of the impulses
+    pub fn envelope(&mut self, envelope: Envelope) {
+        if envelope != self.envelope {
+            self.envelope = envelope;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the length in ms, used by every envelope except the exponential one
+    pub fn length(&mut self, length: f32) {
+        let length = length * 0.001;
+        if length != self.length {
+            self.length = length;
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the breakpoints of the custom envelope as (position, gain) pairs
+    /// sorted by position. Anything past `MAX_POINTS` is ignored.
+    pub fn points(&mut self, points: &[(f32, f32)]) {
+        let points = &points[..points.len().min(MAX_POINTS)];
+        if points != &self.points[..self.num_points] {
+            self.points[..points.len()].copy_from_slice(points);
+            self.num_points = points.len();
+            self.dirty = true;
+        }
+    }
+
+    /// Sets the seed used to place the impulses
+    pub fn seed(&mut self, seed: u32) {
+        if seed != self.seed {
+            self.seed = seed;
+            self.dirty = true;
+        }
+    }
+}
+
+/// The distance between impulses in samples `start` samples into the sequence,
+/// for a sequence that starts with `base_grid`.
+fn grid(base_grid: f32, start: f32, sample_rate: f32) -> f32 {
+    let halvings = (start / (THIN_TIME * sample_rate)) as i32;
+    let sparsest = base_grid.max(sample_rate / MIN_DENSITY);
+    (base_grid * 2.0_f32.powi(halvings.min(30))).min(sparsest)
+}
+
+/// The most taps a sequence can have, at the highest density and length.
+fn max_taps(sample_rate: f32) -> usize {
+    let base_grid = sample_rate / MAX_DENSITY;
+    let length = MAX_LENGTH * sample_rate;
+    let mut start = 0.0;
+    let mut taps = 0;
+    while start < length {
+        start += grid(base_grid, start, sample_rate);
+        taps += 1;
+    }
+    taps + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(n: usize) -> f32 {
+        if n.is_multiple_of(1000) { 1.0 } else { 0.0 }
+    }
+
+    #[test]
+    fn new_sequences_are_faded_in() {
+        let mut velvet = VelvetReverb::new(44100);
+        let mut old = VelvetReverb::new(44100);
+        let mut new = VelvetReverb::new(44100);
+        new.seed(2);
+        for n in 0..20000 {
+            old.process((input(n), input(n)));
+            new.process((input(n), input(n)));
+        }
+
+        for n in 0..20000 {
+            velvet.process((input(n), input(n)));
+        }
+        velvet.seed(2);
+
+        // Nothing changes while the new sequences are generated, then it fades
+        // over to them and ends up just like a reverb that always had them
+        let mut faded = false;
+        for n in 20000..30000 {
+            let out = velvet.process((input(n), input(n)));
+            let (a, b) = (old.process((input(n), input(n))), new.process((input(n), input(n))));
+            if n == 20000 {
+                assert_eq!(out, a);
+            }
+            let between = |x: f32, a: f32, b: f32| x >= a.min(b) - 1e-6 && x <= a.max(b) + 1e-6;
+            assert!(between(out.0, a.0, b.0) && between(out.1, a.1, b.1), "sample {}", n);
+            faded = out == b;
+        }
+        assert!(faded);
+    }
+
+    #[test]
+    fn later_taps_are_sparser() {
+        let velvet = VelvetReverb::new(44100);
+        let taps = &velvet.taps.0;
+        let early = taps.iter().filter(|tap| tap.delay < 4410).count();
+        let late = taps.iter().filter(|tap| (44100..48510).contains(&tap.delay)).count();
+        // 1000 impulses per second at the default size, thinned down to the minimum
+        assert!((90..=110).contains(&early), "{}", early);
+        assert!((15..=25).contains(&late), "{}", late);
+        assert!(taps.len() <= max_taps(44100.0));
+    }
+}
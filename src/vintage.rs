@@ -0,0 +1,178 @@
+// A vintage digital reverb. The comb reverb runs at a low internal rate behind
+// steep input and output filters and 12 bit gain ranging converters, so the
+// reverb itself is band-limited and grainy like the hardware units of the 80s.
+//
+// The engine is built once for BUILD_RATE. Lower rates clock it slower, like
+// running a hardware unit off a slower clock, so the room grows and darkens
+// without reallocating anything on the audio thread.
+
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::bitcrusher::{BitCrusher, Quantizer};
+use crate::reverb::Reverb;
+
+/// The highest internal rate, the engine has its nominal size at this rate
+pub const BUILD_RATE: f32 = 32000.0;
+/// The lowest internal rate
+pub const MIN_RATE: f32 = 20000.0;
+// Cutoff of the input and output filters relative to the internal rate, the
+// classic units rolled off well below their Nyquist frequency
+const FILTER_CUTOFF: f32 = 0.4;
+
+/// Cubic Hermite interpolation between `x[1]` and `x[2]`, `t` from 0.0 to 1.0
+fn hermite(x: &[f32; 4], t: f32) -> f32 {
+    let c1 = 0.5 * (x[2] - x[0]);
+    let c2 = x[0] - 2.5 * x[1] + 2.0 * x[2] - 0.5 * x[3];
+    let c3 = 0.5 * (x[3] - x[0]) + 1.5 * (x[1] - x[2]);
+    ((c3 * t + c2) * t + c1) * t + x[1]
+}
+
+/// Shifts `x` into the newest end of `history`
+fn push(history: &mut [f32; 4], x: f32) {
+    history.rotate_left(1);
+    history[3] = x;
+}
+
+struct Channel {
+    input_filter: (Biquad, Biquad),
+    output_filter: (Biquad, Biquad),
+    // The converters, at the internal rate
+    adc: BitCrusher,
+    dac: BitCrusher,
+    // The last four input samples at the session rate and output samples at
+    // the internal rate, oldest first
+    input: [f32; 4],
+    output: [f32; 4],
+}
+
+impl Channel {
+    fn new(seed: u32) -> Self {
+        let converter = |seed| {
+            let mut crusher = BitCrusher::new(seed);
+            crusher.set_quantizer(Quantizer::Vintage);
+            crusher
+        };
+
+        Self {
+            input_filter: (Biquad::new(), Biquad::new()),
+            output_filter: (Biquad::new(), Biquad::new()),
+            adc: converter(seed),
+            dac: converter(seed + 1),
+            input: [0.0; 4],
+            output: [0.0; 4],
+        }
+    }
+
+    fn set_cutoff(&mut self, cutoff: f32) {
+        for filter in [&mut self.input_filter, &mut self.output_filter] {
+            filter.0.set_lowpass(cutoff, BUTTERWORTH_Q[0]);
+            filter.1.set_lowpass(cutoff, BUTTERWORTH_Q[1]);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input_filter.0.reset();
+        self.input_filter.1.reset();
+        self.output_filter.0.reset();
+        self.output_filter.1.reset();
+        self.adc.reset();
+        self.dac.reset();
+        self.input = [0.0; 4];
+        self.output = [0.0; 4];
+    }
+}
+
+pub struct VintageReverb {
+    reverb: Reverb,
+    sample_rate: f32,
+    rate: f32,
+    // How far the internal clock is between two ticks, from 0.0 to 1.0
+    phase: f32,
+    channels: [Channel; 2],
+}
+
+impl VintageReverb {
+    ///
+    /// Constructor
+    /// * `sample_rate` - the current sample rate
+    pub fn new(sample_rate: usize) -> Self {
+        let mut vintage = Self {
+            reverb: Reverb::new(BUILD_RATE as usize),
+            sample_rate: sample_rate as f32,
+            rate: 0.0,
+            phase: 0.0,
+            channels: [Channel::new(1), Channel::new(3)],
+        };
+
+        vintage.rate(BUILD_RATE);
+        vintage
+    }
+
+    /// Sets the internal sample rate in Hz, it can't go above the session rate
+    pub fn rate(&mut self, rate: f32) {
+        let rate = rate.clamp(MIN_RATE, BUILD_RATE).min(self.sample_rate);
+        if rate != self.rate {
+            self.rate = rate;
+            let cutoff = FILTER_CUTOFF * rate / self.sample_rate;
+            for channel in self.channels.iter_mut() {
+                channel.set_cutoff(cutoff);
+            }
+        }
+    }
+
+    /// The comb reverb inside, for setting its parameters
+    pub fn reverb(&mut self) -> &mut Reverb {
+        &mut self.reverb
+    }
+
+    /// Returns how long the tail lasts in seconds at the current rate
+    pub fn tail_time(&self) -> f32 {
+        self.reverb.decay_time() * BUILD_RATE / self.rate
+    }
+
+    pub fn reset(&mut self) {
+        self.reverb.reset();
+        self.phase = 0.0;
+        for channel in self.channels.iter_mut() {
+            channel.reset();
+        }
+    }
+
+    /// Processes the `input`, returns the reverb signal without the dry signal.
+    pub fn process(&mut self, input: (f32, f32)) -> (f32, f32) {
+        let step = self.rate / self.sample_rate;
+
+        for (channel, x) in self.channels.iter_mut().zip([input.0, input.1]) {
+            let x = channel.input_filter.1.process(channel.input_filter.0.process(x));
+            push(&mut channel.input, x);
+        }
+
+        // The internal rate is never above the session rate, so there is at
+        // most one tick per sample
+        self.phase += step;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+
+            // The tick fell this far between the last two input samples, which
+            // are read one sample late so the interpolation has a sample ahead
+            let t = 1.0 - self.phase / step;
+            let [left, right] = &mut self.channels;
+            let ticked = (
+                left.adc.process(hermite(&left.input, t)),
+                right.adc.process(hermite(&right.input, t)),
+            );
+
+            let wet = self.reverb.process(ticked);
+            push(&mut left.output, left.dac.process(wet.0));
+            push(&mut right.output, right.dac.process(wet.1));
+        }
+
+        // The output is read two ticks late, between the two middle samples
+        let mut out = [0.0; 2];
+        for (out, channel) in out.iter_mut().zip(self.channels.iter_mut()) {
+            let y = hermite(&channel.output, self.phase);
+            *out = channel.output_filter.1.process(channel.output_filter.0.process(y));
+        }
+
+        (out[0], out[1])
+    }
+}
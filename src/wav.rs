@@ -0,0 +1,290 @@
+// A minimal WAV file reader for loading impulse responses. Supports 16, 24 and
+// 32 bit integer PCM as well as 32 bit float files with any number of channels.
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+// Half the length of the windowed sinc kernel used for resampling
+const SINC_HALF_LEN: isize = 16;
+
+pub struct Wav {
+    pub sample_rate: f32,
+    /// One vector of samples per channel
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Wav {
+    /// Reads and decodes the file at `path`.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, &'static str> {
+        let bytes = std::fs::read(path).map_err(|_| "Could not read the file")?;
+        Self::parse(&bytes)
+    }
+
+    /// Decodes a WAV file from memory.
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("Not a WAV file");
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let len = u32::from_le_bytes([
+                bytes[pos + 4],
+                bytes[pos + 5],
+                bytes[pos + 6],
+                bytes[pos + 7],
+            ]) as usize;
+            let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+
+            match id {
+                b"fmt " => format = Some(body),
+                b"data" => data = Some(body),
+                _ => (),
+            }
+
+            // Chunks are padded to an even length
+            pos += 8 + len + (len & 1);
+        }
+
+        let format = format.ok_or("Missing fmt chunk")?;
+        let data = data.ok_or("Missing data chunk")?;
+        if format.len() < 16 {
+            return Err("Invalid fmt chunk");
+        }
+
+        let read_u16 = |at: usize| u16::from_le_bytes([format[at], format[at + 1]]);
+        let mut tag = read_u16(0);
+        let num_channels = read_u16(2) as usize;
+        let sample_rate = u32::from_le_bytes([format[4], format[5], format[6], format[7]]);
+        let bits = read_u16(14) as usize;
+
+        // WAVE_FORMAT_EXTENSIBLE stores the actual format in the sub format GUID
+        if tag == 0xFFFE {
+            if format.len() < 26 {
+                return Err("Invalid fmt chunk");
+            }
+            tag = read_u16(24);
+        }
+
+        if num_channels == 0 || sample_rate == 0 {
+            return Err("Invalid fmt chunk");
+        }
+
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (1, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (1, 24) => |b| {
+                (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0
+            },
+            (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+            (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => return Err("Unsupported sample format"),
+        };
+
+        let width = bits / 8;
+        let frames = data.len() / (width * num_channels);
+        let mut channels = vec![Vec::with_capacity(frames); num_channels];
+        for frame in data.chunks_exact(width * num_channels) {
+            for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(width)) {
+                channel.push(decode(sample));
+            }
+        }
+
+        Ok(Self {
+            sample_rate: sample_rate as f32,
+            channels,
+        })
+    }
+
+    /// Returns a copy of the file resampled to `sample_rate` with a windowed sinc
+    /// interpolator. When downsampling the kernel also acts as anti-aliasing filter.
+    pub fn resampled(&self, sample_rate: f32) -> Self {
+        if sample_rate == self.sample_rate {
+            return Self {
+                sample_rate,
+                channels: self.channels.clone(),
+            };
+        }
+
+        let ratio = sample_rate / self.sample_rate;
+        let cutoff = ratio.min(1.0);
+        let channels = self
+            .channels
+            .iter()
+            .map(|input| {
+                let len = (input.len() as f32 * ratio).ceil() as usize;
+                (0..len)
+                    .map(|n| {
+                        let t = n as f32 / ratio;
+                        let center = t.floor() as isize;
+                        let mut sum = 0.0;
+                        for k in center - SINC_HALF_LEN + 1..=center + SINC_HALF_LEN {
+                            if k < 0 || k as usize >= input.len() {
+                                continue;
+                            }
+                            let x = t - k as f32;
+                            let window =
+                                0.5 + 0.5 * (PI * x / SINC_HALF_LEN as f32).cos();
+                            sum += input[k as usize] * cutoff * sinc(cutoff * x) * window;
+                        }
+                        sum
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a WAV file with a `fmt ` chunk of `format` and a `data` chunk,
+    /// with an odd length chunk in between to check the padding
+    fn file(format: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunks = Vec::new();
+        for (id, body) in [(b"fmt ", format), (b"junk", &[7][..]), (b"data", data)] {
+            chunks.extend_from_slice(id);
+            chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(&chunks);
+        bytes
+    }
+
+    fn format(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let align = channels * bits / 8;
+        let mut format = Vec::new();
+        format.extend_from_slice(&tag.to_le_bytes());
+        format.extend_from_slice(&channels.to_le_bytes());
+        format.extend_from_slice(&sample_rate.to_le_bytes());
+        format.extend_from_slice(&(sample_rate * align as u32).to_le_bytes());
+        format.extend_from_slice(&align.to_le_bytes());
+        format.extend_from_slice(&bits.to_le_bytes());
+        format
+    }
+
+    #[test]
+    fn decodes_16_bit_stereo() {
+        let data: Vec<u8> = [16384_i16, -32768, -16384, 0]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let wav = Wav::parse(&file(&format(1, 2, 44100, 16), &data)).unwrap();
+
+        assert_eq!(wav.sample_rate, 44100.0);
+        assert_eq!(wav.channels, vec![vec![0.5, -0.5], vec![-1.0, 0.0]]);
+    }
+
+    #[test]
+    fn decodes_24_bit_with_sign() {
+        // -0.5 and just under full scale
+        let data = [0x00, 0x00, 0xC0, 0xFF, 0xFF, 0x7F];
+        let wav = Wav::parse(&file(&format(1, 1, 48000, 24), &data)).unwrap();
+
+        assert_eq!(wav.channels[0][0], -0.5);
+        assert!((wav.channels[0][1] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn decodes_extensible_float() {
+        let mut format = format(0xFFFE, 1, 96000, 32);
+        // cbSize, valid bits, channel mask and the sub format GUID
+        format.extend_from_slice(&22_u16.to_le_bytes());
+        format.extend_from_slice(&32_u16.to_le_bytes());
+        format.extend_from_slice(&0_u32.to_le_bytes());
+        format.extend_from_slice(&3_u16.to_le_bytes());
+        format.extend_from_slice(&[0; 14]);
+        let data: Vec<u8> = [0.25_f32, -2.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+
+        let wav = Wav::parse(&file(&format, &data)).unwrap();
+        assert_eq!(wav.sample_rate, 96000.0);
+        assert_eq!(wav.channels, vec![vec![0.25, -2.0]]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(Wav::parse(b"RIFF\0\0\0\0AVI ").is_err());
+        // 8 bit isn't supported
+        assert!(Wav::parse(&file(&format(1, 1, 44100, 8), &[0, 0])).is_err());
+        assert!(Wav::parse(&file(&format(1, 0, 44100, 16), &[0, 0])).is_err());
+        assert!(Wav::parse(&file(&format(1, 1, 44100, 16)[..12], &[0, 0])).is_err());
+    }
+
+    /// A file with a sine of `frequency` at `sample_rate`
+    fn sine(frequency: f32, sample_rate: f32, len: usize) -> Wav {
+        Wav {
+            sample_rate,
+            channels: vec![
+                (0..len)
+                    .map(|n| (2.0 * PI * frequency * n as f32 / sample_rate).sin())
+                    .collect(),
+            ],
+        }
+    }
+
+    /// The largest difference to a sine of `frequency` away from the edges
+    fn sine_error(wav: &Wav, frequency: f32) -> f32 {
+        let channel = &wav.channels[0];
+        let margin = 4 * SINC_HALF_LEN as usize;
+        channel[margin..channel.len() - margin]
+            .iter()
+            .enumerate()
+            .map(|(n, x)| {
+                let t = (n + margin) as f32 / wav.sample_rate;
+                (x - (2.0 * PI * frequency * t).sin()).abs()
+            })
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn resampling_keeps_the_signal() {
+        let wav = sine(1000.0, 44100.0, 4410);
+
+        let same = wav.resampled(44100.0);
+        assert_eq!(same.channels, wav.channels);
+
+        let up = wav.resampled(96000.0);
+        assert_eq!(up.sample_rate, 96000.0);
+        assert_eq!(up.channels[0].len(), 9600);
+        assert!(sine_error(&up, 1000.0) < 0.01);
+
+        let down = wav.resampled(22050.0);
+        assert_eq!(down.channels[0].len(), 2205);
+        assert!(sine_error(&down, 1000.0) < 0.01);
+    }
+
+    #[test]
+    fn downsampling_filters_out_what_does_not_fit() {
+        // 15 kHz is above the Nyquist frequency of 22.05 kHz
+        let down = sine(15000.0, 44100.0, 4410).resampled(22050.0);
+        let margin = 4 * SINC_HALF_LEN as usize;
+        let channel = &down.channels[0];
+        let peak = channel[margin..channel.len() - margin]
+            .iter()
+            .fold(0.0_f32, |a, x| a.max(x.abs()));
+        assert!(peak < 0.1);
+    }
+}
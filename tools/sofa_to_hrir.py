@@ -0,0 +1,62 @@
+#!/usr/bin/env python3
+"""Converts a SOFA file with head related impulse responses to the HRIR format
+the binaural output reads, see the top of src/hrtf.rs for the layout.
+
+Only the SimpleFreeFieldHRIR convention is supported, which is what nearly all
+published HRTF sets use. Needs h5py and numpy:
+
+    pip install h5py numpy
+    python3 tools/sofa_to_hrir.py subject.sofa subject.hrir
+"""
+
+import struct
+import sys
+
+import h5py
+import numpy as np
+
+
+def convert(sofa_path, hrir_path):
+    with h5py.File(sofa_path, "r") as sofa:
+        convention = sofa.attrs.get("SOFAConventions", b"")
+        if isinstance(convention, bytes):
+            convention = convention.decode()
+        if convention != "SimpleFreeFieldHRIR":
+            sys.exit(f"Only SimpleFreeFieldHRIR files are supported, this is {convention}")
+
+        # Measurements x receivers (ears) x samples
+        irs = np.asarray(sofa["Data.IR"], dtype="<f4")
+        sample_rate = int(np.asarray(sofa["Data.SamplingRate"]).flatten()[0])
+        positions = np.asarray(sofa["SourcePosition"], dtype="<f4")
+        position_type = sofa["SourcePosition"].attrs.get("Type", b"spherical")
+        if isinstance(position_type, bytes):
+            position_type = position_type.decode()
+
+    if irs.ndim != 3 or irs.shape[1] != 2:
+        sys.exit("Expected two ears per measurement")
+    if positions.shape[0] == 1:
+        positions = np.repeat(positions, irs.shape[0], axis=0)
+
+    if position_type == "cartesian":
+        x, y, z = positions[:, 0], positions[:, 1], positions[:, 2]
+        azimuth = np.degrees(np.arctan2(y, x))
+        elevation = np.degrees(np.arctan2(z, np.hypot(x, y)))
+    else:
+        azimuth, elevation = positions[:, 0], positions[:, 1]
+
+    count, _, length = irs.shape
+    with open(hrir_path, "wb") as out:
+        out.write(b"HRIR")
+        out.write(struct.pack("<III", sample_rate, count, length))
+        for m in range(count):
+            out.write(struct.pack("<ff", azimuth[m], elevation[m]))
+            out.write(irs[m, 0].tobytes())
+            out.write(irs[m, 1].tobytes())
+
+    print(f"Wrote {count} directions of {length} samples at {sample_rate} Hz")
+
+
+if __name__ == "__main__":
+    if len(sys.argv) != 3:
+        sys.exit("Usage: sofa_to_hrir.py input.sofa output.hrir")
+    convert(sys.argv[1], sys.argv[2])